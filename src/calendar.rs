@@ -0,0 +1,288 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+use rand::Rng;
+
+use crate::components;
+use crate::map;
+use crate::messages;
+use crate::raws;
+use crate::scheduler;
+use crate::spawner;
+
+pub static TURNS_PER_HOUR: u64 = 10;
+pub static HOURS_PER_DAY: u64 = 24;
+pub static DAYS_PER_SEASON: u64 = 10;
+/// The day starts at this hour, which is also when the game begins.
+pub static DAWN: u64 = 6;
+pub static DUSK: u64 = 20;
+/// Rows along the top of the map that are open to the sky.
+pub static SURFACE_ROWS: i32 = 4;
+/// One in this many days something wanders in from the surface.
+pub static WANDERER_CHANCE: i32 = 2;
+pub static MIGRANTS_PER_WAVE: usize = 2;
+
+static MIGRANT_NAMES: [&str; 8] = ["Zon", "Bomrek", "Ast", "Likot", "Mebzuth", "Sodel", "Tekkud", "Olin"];
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Season { Spring, Summer, Autumn, Winter }
+
+impl Season {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Season::Spring => "Spring",
+            Season::Summer => "Summer",
+            Season::Autumn => "Autumn",
+            Season::Winter => "Winter",
+        }
+    }
+
+    fn next(&self) -> Season {
+        match self {
+            Season::Spring => Season::Summer,
+            Season::Summer => Season::Autumn,
+            Season::Autumn => Season::Winter,
+            Season::Winter => Season::Spring,
+        }
+    }
+
+    /// Crops out on the surface only grow in the warmer half of the year. Underground they grow all year round.
+    pub fn surface_growing(&self) -> bool {
+        *self == Season::Spring || *self == Season::Summer
+    }
+
+    /// Weighted creatures that wander in from the surface.
+    pub fn spawn_table(&self) -> &'static [(&'static str, i32)] {
+        match self {
+            Season::Spring => &[("Cave hare", 6), ("Protogoblin", 1)],
+            Season::Summer => &[("Cave hare", 4), ("Protogoblin", 2)],
+            Season::Autumn => &[("Cave hare", 2), ("Protogoblin", 3)],
+            Season::Winter => &[("Protogoblin", 2)],
+        }
+    }
+}
+
+/// The largest unit of time that rolled over on a tick.
+#[derive(PartialEq, PartialOrd, Copy, Clone, Debug)]
+pub enum Rollover { Hour, Day, Season, Year }
+
+pub struct Calendar {
+    /// Turns into the current hour.
+    pub turns: u64,
+    pub hour: u64,
+    /// Starting from 1.
+    pub day: u64,
+    pub season: Season,
+    /// Starting from 1.
+    pub year: u64,
+}
+
+impl Calendar {
+    pub fn new() -> Calendar {
+        Calendar { turns: 0, hour: DAWN, day: 1, season: Season::Spring, year: 1 }
+    }
+
+    /// Advances the calendar by one turn.
+    pub fn tick(&mut self) -> Option<Rollover> {
+        self.turns += 1;
+        if self.turns < TURNS_PER_HOUR {
+            return None;
+        }
+
+        self.turns = 0;
+        self.hour += 1;
+        if self.hour < HOURS_PER_DAY {
+            return Some(Rollover::Hour);
+        }
+
+        self.hour = 0;
+        self.day += 1;
+        if self.day <= DAYS_PER_SEASON {
+            return Some(Rollover::Day);
+        }
+
+        self.day = 1;
+        self.season = self.season.next();
+        if self.season != Season::Spring {
+            return Some(Rollover::Season);
+        }
+
+        self.year += 1;
+        Some(Rollover::Year)
+    }
+
+    /// How much sunlight reaches the surface, from 0.2 at night to 1.0 in the middle of the day.
+    pub fn daylight(&self) -> f32 {
+        if self.hour > DAWN && self.hour < DUSK - 1 {
+            1.0
+        } else if self.hour >= DAWN - 1 && self.hour < DUSK + 1 {
+            0.6
+        } else {
+            0.2
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{:02}:00, {} {}, year {}", self.hour, self.season.name(), self.day, self.year)
+    }
+}
+
+/// Opens up the top of the map to the sky and sinks a shaft from there down to the fort.
+pub fn carve_surface(ecs: &mut World, fort: (i32, i32)) {
+    let mut map = ecs.fetch_mut::<map::Map>();
+    let mut renderables = ecs.write_storage::<components::Renderable>();
+    let mut open: Vec<(i32, i32)> = Vec::new();
+
+    for y in 1..=SURFACE_ROWS {
+        for x in 1..map.width - 1 {
+            open.push((x, y));
+            let idx = map.xy_idx(x, y);
+            map.surface[idx] = true;
+        }
+    }
+    for y in SURFACE_ROWS + 1..=fort.1 {
+        open.push((fort.0, y));
+    }
+
+    for (x, y) in open {
+        if let Some(render) = map.tiles.get(&(x, y)).and_then(|tile| renderables.get_mut(*tile)) {
+            render.glyph = '.';
+        }
+    }
+}
+
+/// Plants a few plump helmets by the fort and some wild strawberries up on the surface.
+pub fn plant_crops(ecs: &mut World, fort: (i32, i32)) {
+    let mut rng = rand::thread_rng();
+    let mut cave_floor: Vec<(i32, i32)> = Vec::new();
+    let mut surface: Vec<(i32, i32)> = Vec::new();
+
+    {
+        let map = ecs.fetch::<map::Map>();
+        let renderables = ecs.read_storage::<components::Renderable>();
+
+        for (coords, tile) in map.tiles.iter() {
+            if renderables.get(*tile).map_or(true, |r| r.glyph != '.') || coords.0 == fort.0 {
+                continue;
+            }
+
+            if map.surface[map.xy_idx(coords.0, coords.1)] {
+                surface.push(*coords);
+            } else if (coords.0 - fort.0).abs() <= 6 && (coords.1 - fort.1).abs() <= 6 {
+                cave_floor.push(*coords);
+            }
+        }
+    }
+
+    for (tiles, produce, surface_crop, count) in [(cave_floor, "Plump helmet", false, 3), (surface, "Wild strawberries", true, 4)].iter_mut() {
+        for _ in 0..*count {
+            if tiles.is_empty() {
+                break;
+            }
+            let (x, y) = tiles.remove(rng.gen_range(0, tiles.len()));
+
+            ecs.create_entity()
+                .with(components::Position{ x, y })
+                .with(components::Renderable{ glyph: '"', color: Color::from_rgba(60, 140, 60, 1.0) })
+                .with(components::Name{ name: format!("{} plant", produce) })
+                .with(components::Crop{ produce: produce.to_string(), growth: 0, ripe_at: 2 * HOURS_PER_DAY as i32, surface: *surface_crop })
+                .build();
+        }
+    }
+}
+
+fn surface_spot(map: &map::Map) -> Option<(i32, i32)> {
+    let mut rng = rand::thread_rng();
+    let spots: Vec<usize> = (0..map.surface.len())
+        .filter(|idx| map.surface[*idx] && map.glyph_map[*idx] != '#' && !map.blocked[*idx])
+        .collect();
+
+    if spots.is_empty() {
+        return None;
+    }
+
+    let idx = spots[rng.gen_range(0, spots.len())] as i32;
+    Some((idx % map.width, idx / map.width))
+}
+
+/// Moves the calendar on every turn, grows crops every hour, brings wanderers in from the surface every day
+/// and migrants and caravans at the start of a season.
+pub struct CalendarSystem {}
+
+impl<'a> System<'a> for CalendarSystem {
+    type SystemData = ( WriteExpect<'a, Calendar>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        ReadExpect<'a, map::Map>,
+                        ReadExpect<'a, raws::Raws>,
+                        Entities<'a>,
+                        Read<'a, LazyUpdate>,
+                        WriteStorage<'a, components::Crop>,
+                        ReadStorage<'a, components::Position>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut calendar, scheduler, map, raws, entities, lazy, mut crops, positions) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        let rollover = match calendar.tick() {
+            Some(rollover) => rollover,
+            None => return
+        };
+
+        for (crop, pos) in (&mut crops, &positions).join() {
+            if crop.surface && !calendar.season.surface_growing() {
+                continue;
+            }
+
+            crop.growth += 1;
+            if crop.growth >= crop.ripe_at {
+                crop.growth = 0;
+                if let Some(template) = raws.item(&crop.produce) {
+                    raws::build_item(lazy.create_entity(&entities), template, pos.x, pos.y);
+                }
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+
+        if rollover >= Rollover::Season {
+            messages::post(format!("{} has come", calendar.season.name()));
+
+            match calendar.season {
+                Season::Spring | Season::Autumn => {
+                    for _ in 0..MIGRANTS_PER_WAVE {
+                        if let Some((x, y)) = surface_spot(&map) {
+                            let name = MIGRANT_NAMES[rng.gen_range(0, MIGRANT_NAMES.len())];
+                            spawner::dwarf(lazy.create_entity(&entities), name, 'd', x, y);
+                            messages::post(format!("{} has migrated to the fort", name));
+                        }
+                    }
+                }
+                Season::Summer => {
+                    if let Some((x, y)) = surface_spot(&map) {
+                        spawner::merchant(lazy.create_entity(&entities), x, y);
+                        messages::post("A caravan has arrived".to_string());
+                    }
+                }
+                Season::Winter => {}
+            }
+        }
+
+        if rollover >= Rollover::Day && rng.gen_range(0, WANDERER_CHANCE) == 0 {
+            let table = calendar.season.spawn_table();
+            let total: i32 = table.iter().map(|(_, weight)| weight).sum();
+            let mut roll = rng.gen_range(0, total);
+
+            for (name, weight) in table.iter() {
+                if roll < *weight {
+                    if let Some((x, y)) = surface_spot(&map) {
+                        spawner::creature(lazy.create_entity(&entities), name, x, y);
+                    }
+                    break;
+                }
+                roll -= weight;
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+use std::cmp;
+
+use crate::components;
+use crate::input;
+use crate::map;
+use crate::Game;
+
+/// How many times their normal size map tiles are drawn at.
+pub static ZOOM_LEVELS: [f32; 4] = [0.75, 1.0, 1.5, 2.0];
+pub static DEFAULT_ZOOM_LEVEL: usize = 1;
+/// Holding the mouse this close to the edge of the map view scrolls it.
+static EDGE_SCROLL_PIXELS: f32 = 12.0;
+/// Frames between steps while edge-scrolling.
+static EDGE_SCROLL_FRAMES: i32 = 4;
+
+/// Puts the middle of the view over a tile.
+pub fn jump_camera(ecs: &World, x: i32, y: i32) {
+    let mut camera = ecs.write_resource::<components::Camera>();
+
+    camera.x = x;
+    camera.y = y;
+    camera.follow_selected = false;
+}
+
+/// How far the map is shifted to put the focus in the middle of the view, without scrolling past the map edges.
+/// A map smaller than the view stays against its top left corner. With an even number of tiles across there is
+/// no middle tile, and the focus sits just left of or above the middle.
+pub fn camera_translation(focus_position: Vector, map_size: Vector, view_size: Vector) -> Vector {
+    let half_screen_edges = Vector::new(((view_size.x - 1.0) * 0.5).floor(), ((view_size.y - 1.0) * 0.5).floor());
+    let furthest = map_size - view_size;
+    let translation = focus_position - half_screen_edges;
+
+    Vector::new(translation.x.min(furthest.x).max(0.0), translation.y.min(furthest.y).max(0.0))
+}
+
+pub fn camera_translate(focus_position: Vector, object_position: Vector, map_size: Vector, view_size: Vector) -> Vector {
+    object_position - camera_translation(focus_position, map_size, view_size)
+}
+
+/// The inverse of `camera_translate`: from a position on the screen back to the map.
+pub fn camera_untranslate(focus_position: Vector, mapped_position: Vector, map_size: Vector, view_size: Vector) -> Vector {
+    mapped_position + camera_translation(focus_position, map_size, view_size)
+}
+
+pub fn camera_zoom(ecs: &World) -> f32 {
+    ZOOM_LEVELS[ecs.fetch::<components::Camera>().zoom_level]
+}
+
+pub fn zoom_camera(ecs: &World, zoom_in: bool) {
+    let mut camera = ecs.write_resource::<components::Camera>();
+
+    camera.zoom_level = if zoom_in {
+        cmp::min(ZOOM_LEVELS.len() - 1, camera.zoom_level + 1)
+    } else {
+        camera.zoom_level.saturating_sub(1)
+    };
+}
+
+/// Goes back to following the selected unit.
+pub fn snap_camera(ecs: &World) {
+    ecs.write_resource::<components::Camera>().follow_selected = true;
+}
+
+/// Scrolls the view while the mouse is held near an edge of it.
+pub fn edge_scroll(game: &mut Game) {
+    game.edge_scroll_timer -= 1;
+    if game.edge_scroll_timer > 0 {
+        return;
+    }
+
+    let pixel = match game.ecs.fetch::<input::InputQueue>().mouse {
+        Some(pixel) => pixel,
+        None => return
+    };
+
+    let layout = &game.screen_layout;
+    let view = Rectangle::new(layout.screen_origin.times(layout.tile_size_pixels), layout.screen_size.times(layout.tile_size_pixels));
+    if !view.contains(pixel) {
+        return;
+    }
+
+    let step = |position: f32, start: f32, length: f32| {
+        if position < start + EDGE_SCROLL_PIXELS { -1 } else if position > start + length - EDGE_SCROLL_PIXELS { 1 } else { 0 }
+    };
+    let (delta_x, delta_y) = (step(pixel.x, view.pos.x, view.size.x), step(pixel.y, view.pos.y, view.size.y));
+
+    if delta_x != 0 || delta_y != 0 {
+        pan_camera(delta_x, delta_y, &game.ecs);
+        game.edge_scroll_timer = EDGE_SCROLL_FRAMES;
+    }
+}
+
+/// Whether a position relative to the top left of the view, in tiles, is inside the view.
+pub fn should_render(mapped_position: Vector, view_size: Vector) -> bool {
+    mapped_position.x >= 0.0 && mapped_position.y >= 0.0 && mapped_position.x < view_size.x && mapped_position.y < view_size.y
+}
+
+/// Scrolls the camera away from the selected unit. The centre of the view doubles as the target for move orders.
+pub fn pan_camera(delta_x: i32, delta_y: i32, ecs: &World) {
+    let player_pos = ecs.fetch::<components::PlayerPosition>();
+    let map = ecs.fetch::<map::Map>();
+    let mut camera = ecs.write_resource::<components::Camera>();
+
+    if camera.follow_selected {
+        camera.x = player_pos.x;
+        camera.y = player_pos.y;
+        camera.follow_selected = false;
+    }
+
+    camera.x = cmp::min(map.width - 1, cmp::max(0, camera.x + delta_x));
+    camera.y = cmp::min(map.height - 1, cmp::max(0, camera.y + delta_y));
+}
+
+pub fn camera_focus(ecs: &World) -> Vector {
+    let camera = ecs.fetch::<components::Camera>();
+
+    if camera.follow_selected {
+        let player_pos = ecs.fetch::<components::PlayerPosition>();
+        Vector::new(player_pos.x, player_pos.y)
+    } else {
+        Vector::new(camera.x, camera.y)
+    }
+}
+
+/// The tile commands act on: the centre of the view while free-scrolling, otherwise the selected unit.
+pub fn cursor_tile(ecs: &World) -> (i32, i32) {
+    let focus = camera_focus(ecs);
+    (focus.x as i32, focus.y as i32)
+}
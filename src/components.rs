@@ -3,11 +3,32 @@ use quicksilver::prelude::*;
 use specs::prelude::*;
 use specs_derive::Component;
 
+use std::collections::HashMap;
+
+use crate::construction;
+use crate::factions;
+use crate::skills;
+use crate::workshops;
+
+/// Position of the currently selected unit.
 pub struct PlayerPosition {
     pub x: i32,
     pub y: i32
 }
 
+pub struct SelectedUnit {
+    pub entity: Option<Entity>
+}
+
+/// Where the map view is centred when it is not following the selected unit, and how far it is zoomed in.
+pub struct Camera {
+    pub x: i32,
+    pub y: i32,
+    pub follow_selected: bool,
+    /// Index into `ZOOM_LEVELS` in main.
+    pub zoom_level: usize
+}
+
 #[derive(Component, Debug)]
 pub struct Name {
     pub name : String
@@ -45,4 +66,263 @@ pub struct Tile{}
 pub struct Monster {}
 
 #[derive(Component)]
-pub struct RandomMover {}
\ No newline at end of file
+pub struct RandomMover {}
+
+#[derive(Component, Debug)]
+pub struct Faction {
+    pub kind: factions::FactionKind
+}
+
+#[derive(Component, Debug)]
+pub struct CombatStats {
+    pub max_hp : i32,
+    pub hp : i32,
+    pub defense : i32,
+    pub power : i32
+}
+
+#[derive(Component, Debug)]
+pub struct WantsToMelee {
+    pub target : Entity
+}
+
+#[derive(Component, Debug)]
+pub struct SufferDamage {
+    pub amount : Vec<i32>
+}
+
+impl SufferDamage {
+    pub fn new_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32) {
+        if let Some(suffering) = store.get_mut(victim) {
+            suffering.amount.push(amount);
+        } else {
+            let dmg = SufferDamage { amount : vec![amount] };
+            store.insert(victim, dmg).expect("Unable to insert damage");
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct Hearing {
+    pub threshold : i32
+}
+
+#[derive(Component, Debug)]
+pub struct Investigating {
+    pub x : i32,
+    pub y : i32,
+    pub loudness : i32
+}
+
+#[derive(Component, Debug)]
+pub struct Dwarf {}
+
+#[derive(Component, Debug)]
+pub struct AssignedJob {
+    pub job_id : usize
+}
+
+#[derive(Component, Debug)]
+pub struct MoveOrder {
+    pub x : i32,
+    pub y : i32
+}
+
+/// Walks towards whatever hasn't been seen yet, until something worth stopping for comes into sight.
+#[derive(Component, Debug)]
+pub struct AutoExplore {
+    /// Items already in sight when exploring started, which shouldn't stop it.
+    pub seen_items : Vec<Entity>
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum NeedKind { Hunger, Thirst, Fatigue }
+
+#[derive(Component, Debug)]
+pub struct Hunger {
+    pub current : i32,
+    pub max : i32
+}
+
+#[derive(Component, Debug)]
+pub struct Thirst {
+    pub current : i32,
+    pub max : i32
+}
+
+#[derive(Component, Debug)]
+pub struct Fatigue {
+    pub current : i32,
+    pub max : i32
+}
+
+#[derive(Component, Debug)]
+pub struct Seeking {
+    pub need : NeedKind,
+    pub target : Entity
+}
+
+#[derive(Component, Debug)]
+pub struct Asleep {}
+
+/// Spent a turn wading towards deep water, and takes the step next time it moves.
+#[derive(Component, Debug)]
+pub struct Wading {}
+
+#[derive(Component, Debug)]
+pub struct Edible {
+    pub nutrition : i32
+}
+
+#[derive(Component, Debug)]
+pub struct Drinkable {
+    pub hydration : i32
+}
+
+#[derive(Component, Debug)]
+pub struct Bed {}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum ItemCategory { Stone, Ore, Wood, Food, Drink, Furniture, Goods }
+
+#[derive(Component, Debug)]
+pub struct Item {
+    pub category : ItemCategory
+}
+
+/// Carried items keep a `Position`, which follows whoever is carrying them.
+#[derive(Component, Debug)]
+pub struct CarriedBy {
+    pub owner : Entity
+}
+
+#[derive(Component, Debug)]
+pub struct Workshop {
+    pub kind : workshops::WorkshopKind,
+    pub built : bool,
+    /// Recipe indices into `Raws::recipes`, worked front to back.
+    pub queue : Vec<usize>,
+    /// Which of the workshop's recipes gets queued next.
+    pub selected_recipe : usize,
+    pub active_job : Option<usize>
+}
+
+#[derive(Component, Debug)]
+pub struct Temperature {
+    pub degrees : i32
+}
+
+#[derive(Component, Debug)]
+pub struct Flammable {
+    /// Temperature at which it catches fire.
+    pub ignition : i32,
+    /// Turns it burns for.
+    pub fuel : i32
+}
+
+#[derive(Component, Debug)]
+pub struct Burning {
+    pub fuel : i32
+}
+
+/// A plant that drops its produce every time it ripens.
+#[derive(Component, Debug)]
+pub struct Crop {
+    /// Item template name of the produce.
+    pub produce : String,
+    /// Hours grown since it last ripened.
+    pub growth : i32,
+    pub ripe_at : i32,
+    pub surface : bool
+}
+
+/// A planned construction. Materials get hauled to it, then a dwarf builds it.
+#[derive(Component, Debug)]
+pub struct Blueprint {
+    pub kind : construction::ConstructionKind,
+    pub build_job : Option<usize>
+}
+
+#[derive(Component, Debug)]
+pub struct Door {
+    pub open : bool,
+    /// Index into `doors::LOCK_PRESETS`.
+    pub lock : usize,
+    /// Turns left before an open door with nobody in the doorway shuts.
+    pub close_in : i32
+}
+
+/// Something dwarves built, and can take down again. Sits on the tile entity for walls and floors.
+#[derive(Component, Debug)]
+pub struct Constructed {
+    pub kind : construction::ConstructionKind
+}
+
+/// Experience in each skill. Levels are worked out from it with `skills::level_for`.
+#[derive(Component, Debug, Default)]
+pub struct Skills {
+    pub experience : HashMap<skills::SkillKind, i32>
+}
+
+impl Skills {
+    pub fn level(&self, kind: skills::SkillKind) -> i32 {
+        skills::level_for(*self.experience.get(&kind).unwrap_or(&0))
+    }
+
+    /// Returns the new level if the experience was enough to level up.
+    pub fn gain(&mut self, kind: skills::SkillKind, amount: i32) -> Option<i32> {
+        let before = self.level(kind);
+        *self.experience.entry(kind).or_insert(0) += amount;
+        let after = self.level(kind);
+
+        if after > before { Some(after) } else { None }
+    }
+}
+
+/// How well something was made.
+#[derive(Component, Debug)]
+pub struct Quality {
+    pub tier : skills::QualityTier
+}
+
+/// Name of the material from `static/materials.txt`.
+#[derive(Component, Debug)]
+pub struct MadeOf {
+    pub material : String
+}
+
+#[derive(Component, Debug)]
+pub struct Weight {
+    pub kilograms : f32
+}
+
+#[derive(Component, Debug)]
+pub struct Value {
+    pub coins : i32
+}
+
+/// Adds to the damage of whoever is carrying it.
+#[derive(Component, Debug)]
+pub struct Weapon {
+    pub damage : i32
+}
+
+/// Adds to the defense of whoever is carrying it.
+#[derive(Component, Debug)]
+pub struct Armour {
+    pub defense : i32
+}
+
+/// Lets whoever is carrying it attack from a distance. Thrown weapons leave their owner's hands.
+#[derive(Component, Debug)]
+pub struct Ranged {
+    pub damage : i32,
+    pub range : i32,
+    pub thrown : bool
+}
+
+#[derive(Component, Debug)]
+pub struct WantsToShoot {
+    pub x : i32,
+    pub y : i32
+}
@@ -0,0 +1,212 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+
+use crate::components;
+use crate::heat;
+use crate::jobs;
+use crate::map;
+use crate::raws;
+use crate::scheduler;
+use crate::workshops;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum ConstructionKind { Wall, Floor, Door, Table, Chair, Bed }
+
+pub static CONSTRUCTION_KINDS: [ConstructionKind; 6] = [ConstructionKind::Wall, ConstructionKind::Floor, ConstructionKind::Door,
+                                                        ConstructionKind::Table, ConstructionKind::Chair, ConstructionKind::Bed];
+
+impl ConstructionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConstructionKind::Wall => "Wall",
+            ConstructionKind::Floor => "Paved floor",
+            ConstructionKind::Door => "Door",
+            ConstructionKind::Table => "Table",
+            ConstructionKind::Chair => "Chair",
+            ConstructionKind::Bed => "Bed",
+        }
+    }
+
+    pub fn glyph(&self) -> char {
+        match self {
+            ConstructionKind::Wall => '#',
+            ConstructionKind::Floor => ',',
+            ConstructionKind::Door => '+',
+            ConstructionKind::Table => 'T',
+            ConstructionKind::Chair => 'c',
+            ConstructionKind::Bed => '_',
+        }
+    }
+
+    /// Items used up by building it, and handed back when it is taken down again.
+    pub fn materials(&self) -> Vec<(String, usize)> {
+        let (name, count) = match self {
+            ConstructionKind::Wall => ("Stone block", 1),
+            ConstructionKind::Floor => ("Stone", 1),
+            ConstructionKind::Door => ("Plank", 2),
+            ConstructionKind::Table => ("Stone block", 1),
+            ConstructionKind::Chair => ("Plank", 1),
+            ConstructionKind::Bed => ("Wooden bed", 1),
+        };
+
+        vec![(name.to_string(), count)]
+    }
+
+    pub fn is_wooden(&self) -> bool {
+        match self {
+            ConstructionKind::Door | ConstructionKind::Chair | ConstructionKind::Bed => true,
+            _ => false
+        }
+    }
+
+    /// Walls and floors change the terrain itself. Everything else, doors included, is an entity standing on it.
+    pub fn is_terrain(&self) -> bool {
+        match self {
+            ConstructionKind::Wall | ConstructionKind::Floor => true,
+            _ => false
+        }
+    }
+}
+
+/// Finds the blueprint or piece of built furniture standing on a tile.
+pub fn construction_at(ecs: &World, x: i32, y: i32) -> Option<Entity> {
+    let entities = ecs.entities();
+    let blueprints = ecs.read_storage::<components::Blueprint>();
+    let constructed = ecs.read_storage::<components::Constructed>();
+    let tiles = ecs.read_storage::<components::Tile>();
+    let positions = ecs.read_storage::<components::Position>();
+
+    (&entities, &positions, !&tiles).join()
+        .filter(|(entity, _, _)| blueprints.get(*entity).is_some() || constructed.get(*entity).is_some())
+        .find(|(_, pos, _)| pos.x == x && pos.y == y)
+        .map(|(entity, _, _)| entity)
+}
+
+/// Lays out a blueprint on a tile. Construction happens on open floor that nothing else has been planned or built on,
+/// and floors can only be paved once.
+pub fn place_blueprint(ecs: &mut World, kind: ConstructionKind, x: i32, y: i32) -> bool {
+    {
+        let map = ecs.fetch::<map::Map>();
+
+        if !map.in_bounds(x, y) {
+            return false;
+        }
+
+        let glyph = map.glyph_map[map.xy_idx(x, y)];
+        let open = if kind == ConstructionKind::Floor { glyph == '.' } else { glyph == '.' || glyph == ',' };
+
+        if !open || construction_at(ecs, x, y).is_some() || workshops::workshop_at(ecs, x, y).is_some() {
+            return false;
+        }
+    }
+
+    ecs.create_entity()
+        .with(components::Position{ x, y })
+        .with(components::Renderable{ glyph: kind.glyph(), color: Color::from_rgba(70, 110, 200, 0.6) })
+        .with(components::Name{ name: format!("{} blueprint", kind.name()) })
+        .with(components::Blueprint{ kind, build_job: None })
+        .build();
+
+    true
+}
+
+/// Marks whatever was built on a tile to be taken down. Returns false if nothing has been built there.
+pub fn designate_deconstruction(ecs: &World, x: i32, y: i32) -> bool {
+    let built_here = {
+        let map = ecs.fetch::<map::Map>();
+        let constructed = ecs.read_storage::<components::Constructed>();
+        let furniture = construction_at(ecs, x, y).map_or(false, |entity| constructed.get(entity).is_some());
+        let terrain = map.tiles.get(&(x, y)).map_or(false, |tile| constructed.get(*tile).is_some());
+
+        furniture || terrain
+    };
+
+    if built_here {
+        ecs.write_resource::<jobs::JobBoard>().designate(jobs::JobKind::Deconstruct, x, y);
+    }
+
+    built_here
+}
+
+pub fn spawn_furniture(lazy: &LazyUpdate, entities: &Entities, kind: ConstructionKind, x: i32, y: i32) -> Entity {
+    let builder = lazy.create_entity(entities)
+        .with(components::Position{ x, y })
+        .with(components::Renderable{ glyph: kind.glyph(), color: Color::from_rgba(110, 70, 30, 1.0) })
+        .with(components::Name{ name: kind.name().to_string() })
+        .with(components::Constructed{ kind })
+        .with(components::Temperature{ degrees: heat::AMBIENT_TEMPERATURE });
+
+    let builder = if kind.is_wooden() {
+        builder.with(components::Flammable{ ignition: heat::WOOD_IGNITION, fuel: heat::WOOD_FUEL })
+    } else {
+        builder
+    };
+
+    match kind {
+        ConstructionKind::Bed => builder.with(components::Bed{}).build(),
+        ConstructionKind::Door => builder.with(components::Door{ open: false, lock: 0, close_in: 0 }).build(),
+        _ => builder.build()
+    }
+}
+
+pub fn return_materials(raws: &raws::Raws, lazy: &LazyUpdate, entities: &Entities, kind: ConstructionKind, x: i32, y: i32) {
+    for (name, count) in kind.materials() {
+        if let Some(template) = raws.item(&name) {
+            for _ in 0..count {
+                raws::build_item(lazy.create_entity(entities), template, x, y);
+            }
+        }
+    }
+}
+
+/// Reserves materials for each blueprint, has them hauled over and posts the job to build it.
+pub struct ConstructionSystem {}
+
+impl<'a> System<'a> for ConstructionSystem {
+    type SystemData = ( WriteExpect<'a, jobs::JobBoard>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        Entities<'a>,
+                        WriteStorage<'a, components::Blueprint>,
+                        ReadStorage<'a, components::Position>,
+                        ReadStorage<'a, components::Name>,
+                        ReadStorage<'a, components::Item>,
+                        ReadStorage<'a, components::CarriedBy>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut board, scheduler, entities, mut blueprints, positions, names, items, carried) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        let mut reserved = board.reserved_items();
+        let loose_items: Vec<(Entity, String, i32, i32)> = (&entities, &items, &positions, &names, !&carried).join()
+            .map(|(item, _, pos, name, _)| (item, name.name.clone(), pos.x, pos.y))
+            .collect();
+
+        for (entity, blueprint, pos) in (&entities, &mut blueprints, &positions).join() {
+            if let Some(job_id) = blueprint.build_job {
+                if board.get(job_id).is_some() {
+                    continue;
+                }
+                blueprint.build_job = None;
+            }
+
+            let materials = match jobs::find_ingredients(&blueprint.kind.materials(), (pos.x, pos.y), &reserved, &loose_items) {
+                Some(materials) => materials,
+                None => continue
+            };
+
+            let job_id = board.post_build(entity, (pos.x, pos.y), materials.iter().map(|(item, _, _)| *item).collect());
+            blueprint.build_job = Some(job_id);
+
+            for (item, x, y) in materials {
+                reserved.push(item);
+
+                if !jobs::at_job_site(x, y, pos.x, pos.y) {
+                    board.post_haul(item, (x, y), (pos.x, pos.y));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use specs::prelude::*;
+
+use crate::components;
+use crate::factions::FactionKind;
+use crate::map;
+use crate::messages;
+use crate::noise;
+use crate::scheduler;
+
+/// Turns an open door waits with nobody in the doorway before it swings shut.
+pub static DOOR_CLOSE_DELAY: i32 = 2;
+
+pub static LOCK_PRESETS: [(&str, &[FactionKind]); 4] = [
+    ("Unlocked", &[]),
+    ("Locked to goblins", &[FactionKind::Goblins]),
+    ("Locked to outsiders", &[FactionKind::Goblins, FactionKind::Wildlife, FactionKind::Merchants]),
+    ("Locked to everyone", &[FactionKind::Dwarves, FactionKind::Goblins, FactionKind::Wildlife, FactionKind::Merchants]),
+];
+
+/// Call before moving something onto a tile. A closed door is opened by walking into it, which takes the turn,
+/// unless it is locked to the mover's faction, and can be heard nearby. Returns whether the mover can step onto the tile now.
+pub fn try_pass(map: &mut map::Map, doors: &mut WriteStorage<components::Door>, noises: &mut noise::NoiseQueue,
+                x: i32, y: i32, faction: Option<FactionKind>) -> bool {
+    let idx = map.xy_idx(x, y);
+
+    if map.locked_out(idx, faction) {
+        return false;
+    }
+
+    let state = match map.doors.get_mut(&idx) {
+        Some(state) => state,
+        None => return true
+    };
+
+    let was_open = state.open;
+    state.open = true;
+
+    if let Some(door) = doors.get_mut(state.entity) {
+        door.open = true;
+        door.close_in = DOOR_CLOSE_DELAY;
+    }
+
+    if !was_open {
+        noises.emit(x, y, noise::DOOR_LOUDNESS);
+    }
+
+    was_open
+}
+
+/// Moves the door under the cursor on to its next lock setting.
+pub fn cycle_lock(ecs: &World, x: i32, y: i32) {
+    let positions = ecs.read_storage::<components::Position>();
+    let mut doors = ecs.write_storage::<components::Door>();
+
+    if let Some((door, _pos)) = (&mut doors, &positions).join().find(|(_, pos)| pos.x == x && pos.y == y) {
+        door.lock = (door.lock + 1) % LOCK_PRESETS.len();
+        messages::post(format!("Door: {}", LOCK_PRESETS[door.lock].0));
+    }
+}
+
+/// Closes doors once their doorway has been empty for a while, keeps the map's view of the doors up to date
+/// and redraws them when they open or shut.
+pub struct DoorSystem {}
+
+impl<'a> System<'a> for DoorSystem {
+    type SystemData = ( WriteExpect<'a, map::Map>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        Entities<'a>,
+                        WriteStorage<'a, components::Door>,
+                        ReadStorage<'a, components::Position>,
+                        WriteStorage<'a, components::Renderable>,
+                        WriteStorage<'a, components::Viewshed>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut map, scheduler, entities, mut doors, positions, mut renderables, mut viewsheds) = data;
+        let mut changed = false;
+
+        map.doors.clear();
+
+        for (entity, door, pos) in (&entities, &mut doors, &positions).join() {
+            let idx = map.xy_idx(pos.x, pos.y);
+
+            if door.open && scheduler.is_new_turn() {
+                if map.blocked[idx] {
+                    door.close_in = DOOR_CLOSE_DELAY;
+                } else {
+                    door.close_in -= 1;
+                    door.open = door.close_in > 0;
+                }
+            }
+
+            let glyph = if door.open { '\'' } else { '+' };
+            if let Some(render) = renderables.get_mut(entity) {
+                if render.glyph != glyph {
+                    render.glyph = glyph;
+                    changed = true;
+                }
+            }
+
+            map.doors.insert(idx, map::DoorState{ entity, open: door.open, locked_for: LOCK_PRESETS[door.lock].1 });
+        }
+
+        // Doors change what can be seen through them
+        if changed {
+            for viewshed in (&mut viewsheds).join() {
+                viewshed.dirty = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum FactionKind { Dwarves, Goblins, Wildlife, Merchants }
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Reaction { Attack, Ignore, Flee, Trade }
+
+/// How members of one faction react to members of another. Lookups are directional:
+/// `reaction(Wildlife, Goblins)` is how an animal feels about a goblin, not the other way around.
+pub struct FactionTable {
+    reactions: HashMap<(FactionKind, FactionKind), Reaction>,
+}
+
+impl FactionTable {
+    pub fn new() -> FactionTable {
+        use FactionKind::*;
+        use Reaction::*;
+
+        let mut table = FactionTable { reactions: HashMap::new() };
+
+        table.set(Goblins, Dwarves, Attack);
+        table.set(Goblins, Merchants, Attack);
+        table.set(Goblins, Wildlife, Attack);
+
+        table.set(Dwarves, Goblins, Attack);
+        table.set(Dwarves, Merchants, Trade);
+
+        table.set(Wildlife, Dwarves, Flee);
+        table.set(Wildlife, Goblins, Flee);
+
+        table.set(Merchants, Dwarves, Trade);
+        table.set(Merchants, Goblins, Flee);
+
+        table
+    }
+
+    pub fn set(&mut self, from: FactionKind, towards: FactionKind, reaction: Reaction) {
+        self.reactions.insert((from, towards), reaction);
+    }
+
+    pub fn reaction(&self, from: FactionKind, towards: FactionKind) -> Reaction {
+        if from == towards {
+            return Reaction::Ignore;
+        }
+
+        match self.reactions.get(&(from, towards)) {
+            Some(reaction) => *reaction,
+            None => Reaction::Ignore
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use crate::components;
+use crate::map;
+use crate::messages;
+use crate::raws;
+use crate::scheduler;
+
+/// Settings used when there is no `fluids.txt` in the working directory.
+static DEFAULT_FLUIDS_RAW: &str = include_str!("../static/fluids.txt");
+/// Players can override the fluid settings with a file of the same format in the working directory.
+static FLUIDS_FILE: &str = "fluids.txt";
+
+pub static MAX_DEPTH: u8 = 7;
+/// Frames between fluid updates when nothing else has been configured.
+pub static FLUID_FRAMES_PER_TICK: u64 = 5;
+/// Water this deep takes anyone wading into it an extra turn for each step, see `wade`.
+pub static WADING_DEPTH: u8 = 3;
+/// Water this deep can't be walked through at all. Magma never can.
+pub static IMPASSABLE_WATER_DEPTH: u8 = 5;
+pub static DROWNING_DAMAGE: i32 = 2;
+pub static MAGMA_DAMAGE: i32 = 10;
+/// One in this many puddles dries up each tick.
+pub static EVAPORATION_CHANCE: i32 = 50;
+
+static AQUIFER_COUNT: usize = 4;
+static AQUIFER_RADIUS: i32 = 2;
+/// The highest the floor gets above its lowest point.
+static MAX_ELEVATION: u8 = 4;
+/// How many times the random floor heights get averaged with their neighbours, the more the gentler the slopes.
+static SLOPE_SMOOTHING_PASSES: usize = 6;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Fluid { Water, Magma }
+
+/// How much fluid is sitting on a tile. A depth of 0 means the tile is dry and `kind` means nothing.
+#[derive(Copy, Clone, Debug)]
+pub struct FluidCell {
+    pub kind: Fluid,
+    pub depth: u8
+}
+
+impl FluidCell {
+    pub fn dry() -> FluidCell {
+        FluidCell { kind: Fluid::Water, depth: 0 }
+    }
+}
+
+pub struct FluidSettings {
+    pub frames_per_tick: u64
+}
+
+impl FluidSettings {
+    /// Reads `fluids.txt` from the working directory, falling back to the default settings. A line that can't be
+    /// used is reported and that setting keeps its default.
+    pub fn load() -> FluidSettings {
+        let raw = std::fs::read_to_string(FLUIDS_FILE).unwrap_or_else(|_| DEFAULT_FLUIDS_RAW.to_string());
+        let mut settings = FluidSettings { frames_per_tick: FLUID_FRAMES_PER_TICK };
+
+        for fields in raws::data_lines(&raw) {
+            match fields.as_slice() {
+                ["frames_per_tick", frames] => match frames.parse::<u64>() {
+                    Ok(frames) if frames > 0 => settings.frames_per_tick = frames,
+                    _ => messages::post(format!("Ignoring '{}' in {}: frames_per_tick must be a whole number above 0", fields.join(" | "), FLUIDS_FILE))
+                },
+                _ => messages::post(format!("Ignoring unknown fluid setting '{}' in {}", fields.join(" | "), FLUIDS_FILE))
+            }
+        }
+
+        settings
+    }
+}
+
+/// Deeper fluid is drawn darker.
+pub fn fluid_colour(cell: FluidCell) -> Color {
+    let shade = cell.depth as f32 / MAX_DEPTH as f32;
+
+    match cell.kind {
+        Fluid::Water => Color::from_rgba((120.0 - 100.0 * shade) as u8, (170.0 - 130.0 * shade) as u8, (255.0 - 95.0 * shade) as u8, 1.0),
+        Fluid::Magma => Color::from_rgba(255, (200.0 - 160.0 * shade) as u8, (50.0 - 50.0 * shade) as u8, 1.0),
+    }
+}
+
+/// Gives the floor gentle hills and hollows for fluids to run down: random heights, smoothed out a few times
+/// over and then stretched back out to the full range.
+fn slope_floor(map: &mut map::Map, rng: &mut impl Rng) {
+    let mut heights: Vec<f32> = (0..map.width * map.height).map(|_| rng.gen_range(0.0, 1.0)).collect();
+
+    for _ in 0..SLOPE_SMOOTHING_PASSES {
+        let previous = heights.clone();
+
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let around = [(x, y), (x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)];
+                let nearby: Vec<f32> = around.iter()
+                    .filter(|(x, y)| map.in_bounds(*x, *y))
+                    .map(|(x, y)| previous[map.xy_idx(*x, *y)])
+                    .collect();
+
+                heights[map.xy_idx(x, y)] = nearby.iter().sum::<f32>() / nearby.len() as f32;
+            }
+        }
+    }
+
+    let lowest = heights.iter().cloned().fold(std::f32::MAX, f32::min);
+    let highest = heights.iter().cloned().fold(std::f32::MIN, f32::max);
+    let range = (highest - lowest).max(std::f32::EPSILON);
+
+    map.elevation = heights.iter().map(|height| ((height - lowest) / range * MAX_ELEVATION as f32).round() as u8).collect();
+}
+
+/// Slopes the floor, hides a few aquifers in the rock, and opens a magma vent and a pond somewhere in the caves away from the fort.
+pub fn place_fluids(ecs: &mut World) {
+    let mut rng = rand::thread_rng();
+    let mut walls: Vec<(i32, i32)> = Vec::new();
+    let mut floor: Vec<(i32, i32)> = Vec::new();
+
+    {
+        let map = ecs.fetch::<map::Map>();
+        let renderables = ecs.read_storage::<components::Renderable>();
+
+        for (coords, tile) in map.tiles.iter() {
+            match renderables.get(*tile).map(|r| r.glyph) {
+                Some('#') => walls.push(*coords),
+                Some('.') => floor.push(*coords),
+                _ => {}
+            }
+        }
+    }
+
+    // Keep the surprises away from where the dwarves start out
+    floor.retain(|(x, y)| (x - 40).abs() + (y - 25).abs() > 15);
+
+    let mut map = ecs.fetch_mut::<map::Map>();
+    slope_floor(&mut *map, &mut rng);
+
+    if !walls.is_empty() {
+        for _ in 0..AQUIFER_COUNT {
+            let (centre_x, centre_y) = walls[rng.gen_range(0, walls.len())];
+
+            for (x, y) in walls.iter().filter(|(x, y)| (x - centre_x).abs() <= AQUIFER_RADIUS && (y - centre_y).abs() <= AQUIFER_RADIUS) {
+                let idx = map.xy_idx(*x, *y);
+                map.aquifers.insert(idx, Fluid::Water);
+            }
+        }
+    }
+
+    if floor.len() >= 2 {
+        let (x, y) = floor.remove(rng.gen_range(0, floor.len()));
+        let idx = map.xy_idx(x, y);
+        map.fluid_sources.insert(idx, Fluid::Magma);
+        map.fluids[idx] = FluidCell { kind: Fluid::Magma, depth: MAX_DEPTH };
+
+        let (x, y) = floor[rng.gen_range(0, floor.len())];
+        let idx = map.xy_idx(x, y);
+        map.fluids[idx] = FluidCell { kind: Fluid::Water, depth: MAX_DEPTH };
+    }
+}
+
+/// Called when a tile has been dug out. Digging into an aquifer lets its contents pour into the tunnel.
+pub fn breach(map: &mut map::Map, x: i32, y: i32) {
+    let idx = map.xy_idx(x, y);
+
+    if let Some(kind) = map.aquifers.remove(&idx) {
+        map.fluid_sources.insert(idx, kind);
+        map.fluids[idx] = FluidCell { kind, depth: MAX_DEPTH };
+        messages::post(format!("The rock gives way and {} pours out!", if kind == Fluid::Water { "water" } else { "magma" }));
+    }
+}
+
+/// Called when a unit is about to step into a tile. Wading into deep water takes two turns: the first is spent
+/// wading and the step is only taken on the second, so each unit is slowed on its own however often it moves.
+/// Returns whether the step can be taken now.
+pub fn wade(map: &map::Map, wading: &mut WriteStorage<components::Wading>, entity: Entity, idx: usize) -> bool {
+    if map.fluids[idx].depth < WADING_DEPTH {
+        wading.remove(entity);
+        return true;
+    }
+
+    if wading.remove(entity).is_some() {
+        return true;
+    }
+
+    wading.insert(entity, components::Wading{}).expect("Unable to insert wading");
+    false
+}
+
+/// Moves fluid from each tile to any open neighbour where its surface, floor height plus depth, is at least two
+/// levels lower. Fluid pours into the lowest neighbours first, so it runs downhill, pools in hollows and settles
+/// into shallow puddles on flat ground. Where water meets magma the magma cools into rock.
+/// Returns whether any terrain changed.
+fn flow(map: &mut map::Map, renderables: &mut WriteStorage<components::Renderable>) -> bool {
+    let mut rng = rand::thread_rng();
+
+    let sources: Vec<(usize, Fluid)> = map.fluid_sources.iter().map(|(idx, kind)| (*idx, *kind)).collect();
+    for (idx, kind) in sources {
+        map.fluids[idx] = FluidCell { kind, depth: MAX_DEPTH };
+    }
+
+    let snapshot = map.fluids.clone();
+    let mut depths: Vec<i32> = snapshot.iter().map(|cell| cell.depth as i32).collect();
+    let mut kinds: Vec<Fluid> = snapshot.iter().map(|cell| cell.kind).collect();
+    let mut solidified: Vec<usize> = Vec::new();
+
+    for (idx, cell) in snapshot.iter().enumerate() {
+        if cell.depth == 0 {
+            continue;
+        }
+
+        if cell.depth == 1 && cell.kind == Fluid::Water && rng.gen_range(0, EVAPORATION_CHANCE) == 0 {
+            depths[idx] = 0;
+            continue;
+        }
+
+        let x = idx as i32 % map.width;
+        let y = idx as i32 / map.width;
+        let mut neighbours: Vec<usize> = [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter()
+            .filter(|(x, y)| map.in_bounds(*x, *y))
+            .map(|(x, y)| map.xy_idx(*x, *y))
+            .filter(|n| map.glyph_map[*n] != '#' && !map.door_closed(*n))
+            .collect();
+
+        // Lowest first, with ties in a random order so nothing drifts in one direction on flat ground
+        neighbours.shuffle(&mut rng);
+        neighbours.sort_by_key(|n| map.elevation[*n] as i32 + depths[*n]);
+
+        let floor = map.elevation[idx] as i32;
+        let mut remaining = cell.depth as i32;
+
+        for n in neighbours {
+            // What has flowed in earlier this tick counts too, otherwise water and magma could end up mixed on one tile
+            let other = snapshot[n];
+            if (other.depth > 0 && other.kind != cell.kind) || (depths[n] > 0 && kinds[n] != cell.kind) {
+                solidified.push(if cell.kind == Fluid::Magma { idx } else { n });
+                continue;
+            }
+
+            if remaining > 0 && map.elevation[n] as i32 + depths[n] + 1 < floor + remaining && depths[n] < MAX_DEPTH as i32 {
+                depths[idx] -= 1;
+                depths[n] += 1;
+                kinds[n] = cell.kind;
+                remaining -= 1;
+            }
+        }
+    }
+
+    for (idx, cell) in map.fluids.iter_mut().enumerate() {
+        cell.depth = depths[idx].max(0) as u8;
+        cell.kind = kinds[idx];
+    }
+
+    for idx in solidified.iter() {
+        map.fluids[*idx] = FluidCell::dry();
+        map.fluid_sources.remove(idx);
+
+        // Cooled magma turns to rock, unless somebody is standing in it
+        if !map.blocked[*idx] {
+            let (x, y) = (*idx as i32 % map.width, *idx as i32 / map.width);
+            map::set_tile_glyph(map, renderables, x, y, '#');
+        }
+    }
+
+    !solidified.is_empty()
+}
+
+/// Spreads water and magma at its own tick rate, and once a turn drowns, burns and destroys whatever is in them.
+pub struct FluidSystem {}
+
+impl<'a> System<'a> for FluidSystem {
+    type SystemData = ( WriteExpect<'a, map::Map>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        ReadExpect<'a, FluidSettings>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::Name>,
+                        ReadStorage<'a, components::Position>,
+                        ReadStorage<'a, components::CombatStats>,
+                        ReadStorage<'a, components::Item>,
+                        ReadStorage<'a, components::CarriedBy>,
+                        WriteStorage<'a, components::SufferDamage>,
+                        WriteStorage<'a, components::Renderable>,
+                        WriteStorage<'a, components::Viewshed>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut map, scheduler, settings, entities, names, positions, combat_stats, items, carried,
+             mut inflict_damage, mut renderables, mut viewsheds) = data;
+
+        if scheduler.frame % settings.frames_per_tick == 0 && flow(&mut *map, &mut renderables) {
+            for viewshed in (&mut viewsheds).join() {
+                viewshed.dirty = true;
+            }
+        }
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        for (entity, pos, _stats) in (&entities, &positions, &combat_stats).join() {
+            let cell = map.fluids[map.xy_idx(pos.x, pos.y)];
+            let name = names.get(entity).map_or("Something", |n| n.name.as_str());
+
+            if cell.depth > 0 && cell.kind == Fluid::Magma {
+                messages::post(format!("{} is burned by the magma", name));
+                components::SufferDamage::new_damage(&mut inflict_damage, entity, MAGMA_DAMAGE);
+            } else if cell.depth >= IMPASSABLE_WATER_DEPTH {
+                messages::post(format!("{} is drowning", name));
+                components::SufferDamage::new_damage(&mut inflict_damage, entity, DROWNING_DAMAGE);
+            }
+        }
+
+        // Magma sets anything lying in it alight, and there isn't much left afterwards
+        for (entity, pos, _item, _loose) in (&entities, &positions, &items, !&carried).join() {
+            let cell = map.fluids[map.xy_idx(pos.x, pos.y)];
+
+            if cell.depth > 0 && cell.kind == Fluid::Magma {
+                entities.delete(entity).expect("Unable to delete");
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+use rand::Rng;
+
+use crate::calendar;
+use crate::components;
+use crate::map;
+use crate::raws;
+
+/// Soil reaches this many rows below the surface, give or take `LAYER_WOBBLE`.
+pub static SOIL_DEPTH: i32 = 4;
+/// Sedimentary rock gives way to igneous rock around this row.
+pub static SEDIMENTARY_BOTTOM: i32 = 26;
+pub static LAYER_WOBBLE: f32 = 3.0;
+/// Veins run where the vein noise is within this distance of its midpoint, so they come out as thin winding bands.
+static VEIN_WIDTH: f32 = 0.04;
+/// Gem clusters form where the gem noise is above this.
+static GEM_THRESHOLD: f32 = 0.82;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Layer { Soil, Sedimentary, Igneous }
+
+/// Something worth digging for, as the item it comes out as and what that item is made of.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Deposit {
+    pub item: &'static str,
+    pub material: &'static str
+}
+
+impl Layer {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layer::Soil => "soil",
+            Layer::Sedimentary => "sedimentary rock",
+            Layer::Igneous => "igneous rock",
+        }
+    }
+
+    /// Materials from `static/materials.txt` the layer is made of.
+    fn rocks(&self) -> &'static [&'static str] {
+        match self {
+            Layer::Soil => &["Clay", "Sand"],
+            Layer::Sedimentary => &["Limestone", "Sandstone"],
+            Layer::Igneous => &["Granite", "Basalt"],
+        }
+    }
+
+    fn ores(&self) -> &'static [Deposit] {
+        match self {
+            Layer::Soil => &[],
+            Layer::Sedimentary => &[Deposit { item: "Iron ore", material: "Hematite" }],
+            Layer::Igneous => &[Deposit { item: "Iron ore", material: "Magnetite" }],
+        }
+    }
+
+    fn gems(&self) -> &'static [Deposit] {
+        match self {
+            Layer::Igneous => &[Deposit { item: "Rough gem", material: "Amethyst" }, Deposit { item: "Rough gem", material: "Emerald" }],
+            _ => &[]
+        }
+    }
+
+    /// Only solid rock yields stone worth carrying off.
+    pub fn yields_stone(&self) -> bool {
+        *self != Layer::Soil
+    }
+}
+
+/// Smooth pseudo-random values between 0 and 1 that drift gently from tile to tile.
+struct ValueNoise {
+    seed: u32,
+    scale: f32
+}
+
+impl ValueNoise {
+    fn new(scale: f32) -> ValueNoise {
+        ValueNoise { seed: rand::thread_rng().gen(), scale }
+    }
+
+    fn lattice(&self, x: i32, y: i32) -> f32 {
+        let mut hash = (x as u32).wrapping_mul(374_761_393) ^ (y as u32).wrapping_mul(668_265_263) ^ self.seed;
+        hash = (hash ^ (hash >> 13)).wrapping_mul(1_274_126_177);
+        hash ^= hash >> 16;
+
+        hash as f32 / u32::max_value() as f32
+    }
+
+    fn at(&self, x: i32, y: i32) -> f32 {
+        let (fx, fy) = (x as f32 * self.scale, y as f32 * self.scale);
+        let (cell_x, cell_y) = (fx.floor() as i32, fy.floor() as i32);
+
+        // Smoothstep between the four surrounding lattice points
+        let (tx, ty) = (fx - fx.floor(), fy - fy.floor());
+        let (sx, sy) = (tx * tx * (3.0 - 2.0 * tx), ty * ty * (3.0 - 2.0 * ty));
+
+        let top = self.lattice(cell_x, cell_y) * (1.0 - sx) + self.lattice(cell_x + 1, cell_y) * sx;
+        let bottom = self.lattice(cell_x, cell_y + 1) * (1.0 - sx) + self.lattice(cell_x + 1, cell_y + 1) * sx;
+
+        top * (1.0 - sy) + bottom * sy
+    }
+}
+
+fn darken(colour: Color, amount: f32) -> Color {
+    Color { r: colour.r * amount, g: colour.g * amount, b: colour.b * amount, a: colour.a }
+}
+
+/// Fills the map with rock: soil near the surface, sedimentary rock below that and igneous rock at the bottom,
+/// each layer mottled between its rock types, with ore veins and gem clusters running through the walls.
+/// Walls are tinted with what they are made of.
+pub fn lay_down_rock(ecs: &mut World) {
+    let layer_noise = ValueNoise::new(0.08);
+    let rock_noise = ValueNoise::new(0.12);
+    let vein_noise = ValueNoise::new(0.15);
+    let gem_noise = ValueNoise::new(0.3);
+    let mut rng = rand::thread_rng();
+
+    let raws = ecs.fetch::<raws::Raws>();
+    let mut map = ecs.fetch_mut::<map::Map>();
+    let mut renderables = ecs.write_storage::<components::Renderable>();
+
+    for y in 0..map.height {
+        for x in 0..map.width {
+            let idx = map.xy_idx(x, y);
+            let layer = layer_at(y, layer_noise.at(x, y));
+
+            let rocks = layer.rocks();
+            let rock = rocks[((rock_noise.at(x, y) * rocks.len() as f32) as usize).min(rocks.len() - 1)];
+            map.rock[idx] = rock;
+
+            let tile = match map.tiles.get(&(x, y)) {
+                Some(tile) => *tile,
+                None => continue
+            };
+            let render = match renderables.get_mut(tile) {
+                Some(render) if render.glyph == '#' => render,
+                _ => continue
+            };
+
+            let ores = layer.ores();
+            let gems = layer.gems();
+            let deposit = if !ores.is_empty() && (vein_noise.at(x, y) - 0.5).abs() < VEIN_WIDTH {
+                Some(ores[rng.gen_range(0, ores.len())])
+            } else if !gems.is_empty() && gem_noise.at(x, y) > GEM_THRESHOLD {
+                Some(gems[rng.gen_range(0, gems.len())])
+            } else {
+                None
+            };
+
+            if let Some(deposit) = deposit {
+                map.deposits.insert(idx, deposit);
+            }
+
+            let shown = deposit.map_or(rock, |d| d.material);
+            if let Some(material) = raws.material(shown) {
+                render.color = if deposit.is_some() { material.colour } else { darken(material.colour, 0.6) };
+            }
+        }
+    }
+}
+
+/// Which layer a row belongs to, nudged up or down by a noise value between 0 and 1 so the boundaries aren't straight.
+pub fn layer_at(y: i32, noise: f32) -> Layer {
+    let depth = y as f32 + (noise - 0.5) * 2.0 * LAYER_WOBBLE;
+
+    if depth < (calendar::SURFACE_ROWS + SOIL_DEPTH) as f32 {
+        Layer::Soil
+    } else if depth < SEDIMENTARY_BOTTOM as f32 {
+        Layer::Sedimentary
+    } else {
+        Layer::Igneous
+    }
+}
+
+/// Which layer a rock type belongs to.
+pub fn layer_of(rock: &str) -> Option<Layer> {
+    [Layer::Soil, Layer::Sedimentary, Layer::Igneous].iter().cloned().find(|layer| layer.rocks().contains(&rock))
+}
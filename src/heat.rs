@@ -0,0 +1,197 @@
+use specs::prelude::*;
+
+use crate::components;
+use crate::fluids::Fluid;
+use crate::map;
+use crate::messages;
+use crate::scheduler;
+use crate::workshops::WorkshopKind;
+
+pub static AMBIENT_TEMPERATURE: i32 = 10;
+pub static MAGMA_TEMPERATURE: i32 = 1200;
+pub static FIRE_TEMPERATURE: i32 = 800;
+/// How hot magma and fire make the tiles right next to them.
+pub static RADIANT_TEMPERATURE: i32 = 400;
+pub static FORGE_TEMPERATURE: i32 = 200;
+/// Creatures standing on a tile at least this hot get burned.
+pub static SCALDING_TEMPERATURE: i32 = 100;
+pub static BURN_DAMAGE: i32 = 3;
+/// Turns a tile keeps burning after the last fuel on it is gone.
+pub static FIRE_LINGER: u8 = 2;
+pub static MAX_SMOKE: u8 = 8;
+/// Once the smoke along a line of sight adds up to more than this, whatever is behind it can't be seen.
+pub static SMOKE_SIGHT_LIMIT: i32 = 6;
+pub static WOOD_IGNITION: i32 = 300;
+pub static WOOD_FUEL: i32 = 20;
+
+fn neighbours(map: &map::Map, idx: usize) -> Vec<usize> {
+    let x = idx as i32 % map.width;
+    let y = idx as i32 / map.width;
+
+    [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter()
+        .filter(|(nx, ny)| map.in_bounds(*nx, *ny))
+        .map(|(nx, ny)| map.xy_idx(*nx, *ny))
+        .collect()
+}
+
+/// Whether smoke hangs too thick between two tiles to see from one to the other.
+pub fn smoke_blocks_sight(map: &map::Map, from: (i32, i32), to: (i32, i32)) -> bool {
+    let smoke: i32 = map::line(from, to).iter()
+        .filter(|point| **point != from && **point != to)
+        .map(|(x, y)| map.smoke[map.xy_idx(*x, *y)] as i32)
+        .sum();
+
+    smoke > SMOKE_SIGHT_LIMIT
+}
+
+/// Once a turn: spreads heat from magma, fires and forges, sets alight whatever gets hot enough,
+/// burns up fuel, lets smoke drift and burns creatures caught in the heat.
+pub struct HeatSystem {}
+
+impl<'a> System<'a> for HeatSystem {
+    type SystemData = ( WriteExpect<'a, map::Map>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::Name>,
+                        ReadStorage<'a, components::Position>,
+                        ReadStorage<'a, components::Workshop>,
+                        ReadStorage<'a, components::Flammable>,
+                        WriteStorage<'a, components::Temperature>,
+                        WriteStorage<'a, components::Burning>,
+                        ReadStorage<'a, components::CombatStats>,
+                        WriteStorage<'a, components::SufferDamage>,
+                        WriteStorage<'a, components::Viewshed>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut map, scheduler, entities, names, positions, workshops, flammables, mut temperatures, mut burning,
+             combat_stats, mut inflict_damage, mut viewsheds) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        let was_smoky = map.smoke.iter().any(|smoke| *smoke > 0);
+
+        // Heat conducts towards the neighbours' average and slowly settles back to ambient
+        let snapshot = map.temperature.clone();
+        for idx in 0..snapshot.len() {
+            let around = neighbours(&map, idx);
+            let total: i32 = snapshot[idx] + around.iter().map(|n| snapshot[*n]).sum::<i32>();
+            let average = total / (around.len() as i32 + 1);
+
+            map.temperature[idx] = average - (average - AMBIENT_TEMPERATURE) / 10;
+        }
+
+        for idx in 0..snapshot.len() {
+            let cell = map.fluids[idx];
+            let source = if cell.depth > 0 && cell.kind == Fluid::Magma {
+                MAGMA_TEMPERATURE
+            } else if map.fire[idx] > 0 {
+                FIRE_TEMPERATURE
+            } else {
+                continue;
+            };
+
+            map.temperature[idx] = i32::max(map.temperature[idx], source);
+            for n in neighbours(&map, idx) {
+                if map.glyph_map[n] != '#' {
+                    map.temperature[n] = i32::max(map.temperature[n], RADIANT_TEMPERATURE);
+                }
+            }
+        }
+
+        for (workshop, pos) in (&workshops, &positions).join() {
+            if workshop.built && workshop.kind == WorkshopKind::Smith {
+                let idx = map.xy_idx(pos.x, pos.y);
+                map.temperature[idx] = i32::max(map.temperature[idx], FORGE_TEMPERATURE);
+            }
+        }
+
+        // Water soaks up the heat
+        for idx in 0..snapshot.len() {
+            let cell = map.fluids[idx];
+            if cell.depth > 0 && cell.kind == Fluid::Water {
+                map.temperature[idx] = AMBIENT_TEMPERATURE;
+            }
+        }
+
+        // Items and furniture take on the temperature around them, and catch fire once it passes their ignition point
+        let mut ignited: Vec<(Entity, i32)> = Vec::new();
+        for (entity, pos, temperature) in (&entities, &positions, &mut temperatures).join() {
+            let idx = map.xy_idx(pos.x, pos.y);
+            temperature.degrees += (map.temperature[idx] - temperature.degrees) / 2;
+
+            if let Some(flammable) = flammables.get(entity) {
+                if temperature.degrees >= flammable.ignition && burning.get(entity).is_none() && map.fluids[idx].depth == 0 {
+                    ignited.push((entity, flammable.fuel));
+                }
+            }
+        }
+
+        for (entity, fuel) in ignited {
+            if let Some(name) = names.get(entity) {
+                messages::post(format!("{} catches fire", name.name));
+            }
+            burning.insert(entity, components::Burning{ fuel }).expect("Unable to set fire");
+        }
+
+        for fire in map.fire.iter_mut() {
+            *fire = fire.saturating_sub(1);
+        }
+
+        let mut extinguished: Vec<Entity> = Vec::new();
+        for (entity, pos, fire) in (&entities, &positions, &mut burning).join() {
+            let idx = map.xy_idx(pos.x, pos.y);
+            let name = names.get(entity).map_or("Something", |n| n.name.as_str());
+
+            if map.fluids[idx].depth > 0 && map.fluids[idx].kind == Fluid::Water {
+                messages::post(format!("{} is put out", name));
+                extinguished.push(entity);
+                continue;
+            }
+
+            map.fire[idx] = FIRE_LINGER;
+            map.smoke[idx] = MAX_SMOKE;
+
+            fire.fuel -= 1;
+            if fire.fuel <= 0 {
+                messages::post(format!("{} burns away", name));
+                entities.delete(entity).expect("Unable to delete");
+            }
+        }
+
+        for entity in extinguished {
+            burning.remove(entity);
+        }
+
+        // Smoke drifts into open neighbouring tiles, thinning out as it goes
+        let smoke = map.smoke.clone();
+        for idx in 0..smoke.len() {
+            if map.glyph_map[idx] == '#' {
+                map.smoke[idx] = 0;
+                continue;
+            }
+
+            let drifted = neighbours(&map, idx).iter().map(|n| smoke[*n].saturating_sub(2)).max().unwrap_or(0);
+            map.smoke[idx] = u8::max(smoke[idx].saturating_sub(1), drifted);
+        }
+
+        for (entity, pos, _stats) in (&entities, &positions, &combat_stats).join() {
+            let idx = map.xy_idx(pos.x, pos.y);
+
+            if map.fire[idx] > 0 || map.temperature[idx] >= SCALDING_TEMPERATURE {
+                if let Some(name) = names.get(entity) {
+                    messages::post(format!("{} is burned", name.name));
+                }
+                components::SufferDamage::new_damage(&mut inflict_damage, entity, BURN_DAMAGE);
+            }
+        }
+
+        // Smoke coming and going changes what everyone can see
+        if was_smoky || map.smoke.iter().any(|smoke| *smoke > 0) {
+            for viewshed in (&mut viewsheds).join() {
+                viewshed.dirty = true;
+            }
+        }
+    }
+}
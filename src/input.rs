@@ -0,0 +1,436 @@
+use quicksilver::prelude::*;
+use quicksilver::input::{ Keyboard, Mouse };
+
+use crate::messages;
+
+/// Bindings used when there is no `keys.txt` in the working directory.
+static DEFAULT_KEYS_RAW: &str = include_str!("../static/keys.txt");
+/// Players can override the default bindings with a file of the same format in the working directory.
+pub static KEYS_FILE: &str = "keys.txt";
+
+/// Everything the player can ask for, independent of which keys ask for it.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Action {
+    MoveNorth, MoveSouth, MoveEast, MoveWest,
+    MoveNorthEast, MoveNorthWest, MoveSouthEast, MoveSouthWest,
+    Wait,
+    PickUp,
+    /// Held down while moving to dig the tile in that direction instead.
+    Dig,
+    /// Held down while moving to scroll the camera instead.
+    Pan,
+    Pause,
+    Quit,
+    Inventory,
+    UnitInfo,
+    SelectNext,
+    MoveOrder,
+    Shout,
+    Stockpile,
+    StockpileFilter,
+    PlaceWorkshop,
+    NextWorkshop,
+    QueueRecipe,
+    NextRecipe,
+    BuildMode,
+    PlaceBlueprint,
+    Deconstruct,
+    CycleLock,
+    Look,
+    Fire,
+    Explore,
+    TravelUp,
+    MarkLocation,
+    TravelToMark,
+    ZoomIn,
+    ZoomOut,
+    SnapCamera,
+    NextTileset,
+}
+
+static ACTION_NAMES: [(&str, Action); 39] = [
+    ("move_north", Action::MoveNorth), ("move_south", Action::MoveSouth), ("move_east", Action::MoveEast), ("move_west", Action::MoveWest),
+    ("move_north_east", Action::MoveNorthEast), ("move_north_west", Action::MoveNorthWest),
+    ("move_south_east", Action::MoveSouthEast), ("move_south_west", Action::MoveSouthWest),
+    ("wait", Action::Wait), ("pick_up", Action::PickUp), ("dig", Action::Dig), ("pan", Action::Pan),
+    ("pause", Action::Pause), ("quit", Action::Quit), ("inventory", Action::Inventory), ("unit_info", Action::UnitInfo),
+    ("select_next", Action::SelectNext), ("move_order", Action::MoveOrder), ("shout", Action::Shout),
+    ("stockpile", Action::Stockpile), ("stockpile_filter", Action::StockpileFilter),
+    ("place_workshop", Action::PlaceWorkshop), ("next_workshop", Action::NextWorkshop),
+    ("queue_recipe", Action::QueueRecipe), ("next_recipe", Action::NextRecipe),
+    ("build_mode", Action::BuildMode), ("place_blueprint", Action::PlaceBlueprint),
+    ("deconstruct", Action::Deconstruct), ("cycle_lock", Action::CycleLock),
+    ("look", Action::Look), ("fire", Action::Fire),
+    ("explore", Action::Explore), ("travel_up", Action::TravelUp),
+    ("mark_location", Action::MarkLocation), ("travel_to_mark", Action::TravelToMark),
+    ("zoom_in", Action::ZoomIn), ("zoom_out", Action::ZoomOut), ("snap_camera", Action::SnapCamera),
+    ("next_tileset", Action::NextTileset),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTION_NAMES.iter().find(|(action_name, _)| *action_name == name).map(|(_, action)| *action)
+    }
+
+    /// Modifiers are acted on while their key is held, rather than once when it is pressed.
+    pub fn is_modifier(&self) -> bool {
+        *self == Action::Dig || *self == Action::Pan
+    }
+
+    /// The step a movement action takes, if it is one.
+    pub fn direction(&self) -> Option<(i32, i32)> {
+        match self {
+            Action::MoveNorth => Some((0, -1)),
+            Action::MoveSouth => Some((0, 1)),
+            Action::MoveEast => Some((1, 0)),
+            Action::MoveWest => Some((-1, 0)),
+            Action::MoveNorthEast => Some((1, -1)),
+            Action::MoveNorthWest => Some((-1, -1)),
+            Action::MoveSouthEast => Some((1, 1)),
+            Action::MoveSouthWest => Some((-1, 1)),
+            _ => None
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Binding {
+    pub key: Key,
+    pub shift: bool
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Preset { Arrows, Vi, Numpad }
+
+impl Preset {
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name {
+            "arrows" => Some(Preset::Arrows),
+            "vi" => Some(Preset::Vi),
+            "numpad" => Some(Preset::Numpad),
+            _ => None
+        }
+    }
+
+    fn movement(&self) -> Vec<(Key, Action)> {
+        match self {
+            Preset::Arrows => vec![
+                (Key::Up, Action::MoveNorth), (Key::Down, Action::MoveSouth), (Key::Right, Action::MoveEast), (Key::Left, Action::MoveWest),
+                (Key::Period, Action::Wait),
+            ],
+            Preset::Vi => vec![
+                (Key::K, Action::MoveNorth), (Key::J, Action::MoveSouth), (Key::L, Action::MoveEast), (Key::H, Action::MoveWest),
+                (Key::U, Action::MoveNorthEast), (Key::Y, Action::MoveNorthWest), (Key::N, Action::MoveSouthEast), (Key::B, Action::MoveSouthWest),
+                (Key::Period, Action::Wait),
+            ],
+            Preset::Numpad => vec![
+                (Key::Numpad8, Action::MoveNorth), (Key::Numpad2, Action::MoveSouth), (Key::Numpad6, Action::MoveEast), (Key::Numpad4, Action::MoveWest),
+                (Key::Numpad9, Action::MoveNorthEast), (Key::Numpad7, Action::MoveNorthWest), (Key::Numpad3, Action::MoveSouthEast), (Key::Numpad1, Action::MoveSouthWest),
+                (Key::Numpad5, Action::Wait),
+            ],
+        }
+    }
+}
+
+/// Which keys trigger which actions.
+pub struct Keymap {
+    pub bindings: Vec<(Binding, Action)>
+}
+
+impl Keymap {
+    /// Reads `keys.txt` from the working directory. Without one, or if it has a mistake in it, the default
+    /// bindings are used instead.
+    pub fn load() -> Keymap {
+        let raw = match std::fs::read_to_string(KEYS_FILE) {
+            Ok(raw) => raw,
+            Err(_) => return Keymap::defaults()
+        };
+
+        Keymap::parse(&raw).unwrap_or_else(|error| {
+            messages::post(format!("Could not use {}: {}. Using the default key bindings instead.", KEYS_FILE, error));
+            Keymap::defaults()
+        })
+    }
+
+    fn defaults() -> Keymap {
+        Keymap::parse(DEFAULT_KEYS_RAW).expect("The default key bindings should parse")
+    }
+
+    /// Parses a key file. A `preset` line lays down the movement keys of that preset, and every
+    /// `action | keys` line after it binds more keys, taking them over from whatever had them before.
+    /// Returns what is wrong with the first line that can't be used.
+    pub fn parse(raw: &str) -> std::result::Result<Keymap, String> {
+        let mut keymap = Keymap { bindings: Vec::new() };
+
+        let lines = raw.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        for line in lines {
+            let fields: Vec<&str> = line.split('|').map(|field| field.trim()).collect();
+            if fields.len() != 2 {
+                return Err(format!("key bindings need an action and its keys: '{}'", line));
+            }
+
+            if fields[0] == "preset" {
+                let preset = Preset::from_name(fields[1]).ok_or_else(|| format!("unknown key preset '{}'", fields[1]))?;
+                keymap.apply_preset(preset);
+                continue;
+            }
+
+            let action = Action::from_name(fields[0]).ok_or_else(|| format!("unknown action '{}'", fields[0]))?;
+            for key in fields[1].split(',').map(|key| key.trim()).filter(|key| !key.is_empty()) {
+                let binding = parse_binding(key).ok_or_else(|| format!("unknown key '{}' for {}", key, fields[0]))?;
+                keymap.bind(binding, action);
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    pub fn apply_preset(&mut self, preset: Preset) {
+        for (key, action) in preset.movement() {
+            self.bind(Binding { key, shift: false }, action);
+        }
+    }
+
+    /// Binds a key to an action. A key only ever does one thing, so this takes it away from anything else.
+    pub fn bind(&mut self, binding: Binding, action: Action) {
+        self.bindings.retain(|(existing, _)| *existing != binding);
+        self.bindings.push((binding, action));
+    }
+
+    fn action_for(&self, binding: Binding) -> Option<Action> {
+        self.bindings.iter().find(|(existing, _)| *existing == binding).map(|(_, action)| *action)
+    }
+
+    /// What a single key press asks for. A shifted key that isn't bound to anything does what it does unshifted.
+    pub fn resolve(&self, binding: Binding) -> Option<Action> {
+        self.action_for(binding).or_else(|| {
+            if binding.shift { self.action_for(Binding { key: binding.key, shift: false }) } else { None }
+        })
+    }
+}
+
+/// Parses "W", "Shift+W", "Numpad8" and so on.
+pub fn parse_binding(text: &str) -> Option<Binding> {
+    let (shift, name) = if text.starts_with("Shift+") { (true, &text[6..]) } else { (false, text) };
+    key_from_name(name).map(|key| Binding { key, shift })
+}
+
+fn key_from_name(name: &str) -> Option<Key> {
+    let letters = [Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+                   Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z];
+    let digits = [Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+    let numpad = [Key::Numpad0, Key::Numpad1, Key::Numpad2, Key::Numpad3, Key::Numpad4,
+                  Key::Numpad5, Key::Numpad6, Key::Numpad7, Key::Numpad8, Key::Numpad9];
+
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_uppercase() {
+            return Some(letters[(c as u8 - b'A') as usize]);
+        }
+        if c.is_ascii_digit() {
+            return Some(digits[(c as u8 - b'0') as usize]);
+        }
+    }
+
+    if name.starts_with("Numpad") {
+        return name[6..].parse::<usize>().ok().and_then(|n| numpad.get(n).cloned());
+    }
+
+    match name {
+        "Up" => Some(Key::Up),
+        "Down" => Some(Key::Down),
+        "Left" => Some(Key::Left),
+        "Right" => Some(Key::Right),
+        "Return" => Some(Key::Return),
+        "Escape" => Some(Key::Escape),
+        "Tab" => Some(Key::Tab),
+        "Space" => Some(Key::Space),
+        "Period" => Some(Key::Period),
+        "Comma" => Some(Key::Comma),
+        "Slash" => Some(Key::Slash),
+        "Semicolon" => Some(Key::Semicolon),
+        "Minus" => Some(Key::Minus),
+        "Equals" => Some(Key::Equals),
+        "Back" => Some(Key::Back),
+        "Home" => Some(Key::Home),
+        "End" => Some(Key::End),
+        "PageUp" => Some(Key::PageUp),
+        "PageDown" => Some(Key::PageDown),
+        "LShift" => Some(Key::LShift),
+        "RShift" => Some(Key::RShift),
+        _ => None
+    }
+}
+
+/// Actions and clicks waiting to be handled this frame. The keyboard and mouse fill it, but anything else can too,
+/// which lets input be played back without either.
+#[derive(Default)]
+pub struct InputQueue {
+    pressed: Vec<Action>,
+    held: Vec<Action>,
+    /// Mouse buttons pressed this frame, with the pixel they were pressed at.
+    clicks: Vec<(MouseButton, Vector)>,
+    /// Where the mouse pointer is, in pixels.
+    pub mouse: Option<Vector>
+}
+
+impl InputQueue {
+    pub fn inject(&mut self, action: Action) {
+        self.pressed.push(action);
+    }
+
+    /// Holds a modifier down until the queue is next cleared.
+    pub fn hold(&mut self, action: Action) {
+        self.held.push(action);
+    }
+
+    pub fn is_held(&self, action: Action) -> bool {
+        self.held.contains(&action)
+    }
+
+    /// Removes an action from the queue, returning whether it was there.
+    pub fn take(&mut self, action: Action) -> bool {
+        let before = self.pressed.len();
+        self.pressed.retain(|pressed| *pressed != action);
+        self.pressed.len() != before
+    }
+
+    pub fn drain(&mut self) -> Vec<Action> {
+        self.pressed.drain(..).collect()
+    }
+
+    pub fn click(&mut self, button: MouseButton, position: Vector) {
+        self.clicks.push((button, position));
+    }
+
+    pub fn drain_clicks(&mut self) -> Vec<(MouseButton, Vector)> {
+        self.clicks.drain(..).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.pressed.clear();
+        self.held.clear();
+        self.clicks.clear();
+    }
+
+    pub fn read_mouse(&mut self, mouse: &Mouse) {
+        self.mouse = Some(mouse.pos());
+
+        for button in [MouseButton::Left, MouseButton::Right].iter() {
+            if mouse[*button] == ButtonState::Pressed {
+                self.clicks.push((*button, mouse.pos()));
+            }
+        }
+    }
+
+    /// Turns this frame's key presses into actions. With shift held, a key's shifted binding wins over its plain one.
+    pub fn read_keyboard(&mut self, keyboard: &Keyboard, keymap: &Keymap) {
+        let shift = keyboard[Key::LShift].is_down() || keyboard[Key::RShift].is_down();
+
+        for (binding, action) in keymap.bindings.iter() {
+            if action.is_modifier() {
+                if keyboard[binding.key].is_down() && !self.held.contains(action) {
+                    self.held.push(*action);
+                }
+                continue;
+            }
+
+            if keyboard[binding.key] != ButtonState::Pressed || binding.shift != shift {
+                // Fall back on the plain binding when the shifted key isn't bound to anything
+                let unshifted = shift && !binding.shift && keymap.action_for(Binding { key: binding.key, shift: true }).is_none();
+                if !(unshifted && keyboard[binding.key] == ButtonState::Pressed) {
+                    continue;
+                }
+            }
+
+            self.pressed.push(*action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(key: Key) -> Binding {
+        Binding { key, shift: false }
+    }
+
+    #[test]
+    fn parses_bindings() {
+        assert_eq!(parse_binding("W"), Some(plain(Key::W)));
+        assert_eq!(parse_binding("Shift+W"), Some(Binding { key: Key::W, shift: true }));
+        assert_eq!(parse_binding("7"), Some(plain(Key::Key7)));
+        assert_eq!(parse_binding("Numpad8"), Some(plain(Key::Numpad8)));
+        assert_eq!(parse_binding("Shift+Comma"), Some(Binding { key: Key::Comma, shift: true }));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert_eq!(parse_binding("w"), None);
+        assert_eq!(parse_binding("Numpad10"), None);
+        assert_eq!(parse_binding("Shift+"), None);
+        assert_eq!(parse_binding("Banana"), None);
+    }
+
+    #[test]
+    fn later_lines_take_keys_over() {
+        let keymap = Keymap::parse("wait | W\n# comment\n\nshout | W, X").unwrap();
+
+        assert_eq!(keymap.action_for(plain(Key::W)), Some(Action::Shout));
+        assert_eq!(keymap.action_for(plain(Key::X)), Some(Action::Shout));
+    }
+
+    #[test]
+    fn presets_take_movement_keys_over() {
+        let keymap = Keymap::parse("shout | K\npreset | vi").unwrap();
+
+        assert_eq!(keymap.action_for(plain(Key::K)), Some(Action::MoveNorth));
+        assert_eq!(keymap.action_for(plain(Key::Up)), None);
+    }
+
+    #[test]
+    fn shifted_keys_fall_back_on_their_plain_binding() {
+        let keymap = Keymap::parse("unit_info | I\ninventory | Shift+I\nshout | Y").unwrap();
+
+        assert_eq!(keymap.resolve(plain(Key::I)), Some(Action::UnitInfo));
+        assert_eq!(keymap.resolve(Binding { key: Key::I, shift: true }), Some(Action::Inventory));
+        assert_eq!(keymap.resolve(Binding { key: Key::Y, shift: true }), Some(Action::Shout));
+        assert_eq!(keymap.resolve(Binding { key: Key::Z, shift: true }), None);
+    }
+
+    #[test]
+    fn default_keys_parse() {
+        let keymap = Keymap::parse(DEFAULT_KEYS_RAW).unwrap();
+
+        assert_eq!(keymap.action_for(plain(Key::Up)), Some(Action::MoveNorth));
+        assert_eq!(keymap.action_for(plain(Key::I)), Some(Action::UnitInfo));
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        assert!(Keymap::parse("fly | F").err().unwrap().contains("fly"));
+        assert!(Keymap::parse("wait | Banana").err().unwrap().contains("Banana"));
+        assert!(Keymap::parse("preset | emacs").err().unwrap().contains("emacs"));
+        assert!(Keymap::parse("wait").is_err());
+    }
+
+    #[test]
+    fn injected_actions_are_taken_once() {
+        let mut queue = InputQueue::default();
+        queue.inject(Action::Pause);
+        queue.inject(Action::Wait);
+        queue.hold(Action::Dig);
+
+        assert!(queue.take(Action::Pause));
+        assert!(!queue.take(Action::Pause));
+        assert!(queue.is_held(Action::Dig));
+        assert_eq!(queue.drain(), vec![Action::Wait]);
+
+        queue.clear();
+        assert!(!queue.is_held(Action::Dig));
+    }
+}
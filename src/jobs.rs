@@ -0,0 +1,622 @@
+use specs::prelude::*;
+use rand::Rng;
+
+use crate::components;
+use crate::construction;
+use crate::doors;
+use crate::fluids;
+use crate::geology;
+use crate::map;
+use crate::materials;
+use crate::messages;
+use crate::noise;
+use crate::raws;
+use crate::scheduler;
+use crate::skills;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum JobKind { Dig, Haul, Build, Craft, Deconstruct }
+
+impl JobKind {
+    /// Turns of work a dwarf has to put in at the job site before the job is done.
+    pub fn work_required(&self) -> i32 {
+        match self {
+            JobKind::Dig => 10,
+            JobKind::Haul => 1,
+            JobKind::Build => 15,
+            JobKind::Craft => 20,
+            JobKind::Deconstruct => 10,
+        }
+    }
+}
+
+pub struct Job {
+    pub id: usize,
+    pub kind: JobKind,
+    pub x: i32,
+    pub y: i32,
+    pub worker: Option<Entity>,
+    pub progress: i32,
+    /// The item a haul job moves.
+    pub item: Option<Entity>,
+    /// Where a haul job takes its item. Cleared once the item is picked up, at which point
+    /// the job's own position becomes the drop-off tile.
+    pub destination: Option<(i32, i32)>,
+    /// The entity being built or worked at, e.g. a workshop or a blueprint.
+    pub target: Option<Entity>,
+    pub recipe: Option<usize>,
+    /// Items a craft or build job consumes. The job can't be started until they have all been hauled to the job site.
+    pub ingredients: Vec<Entity>,
+    pub work_required: i32,
+}
+
+impl Job {
+    fn new(id: usize, kind: JobKind, x: i32, y: i32) -> Job {
+        Job {
+            id,
+            kind,
+            x,
+            y,
+            worker: None,
+            progress: 0,
+            item: None,
+            destination: None,
+            target: None,
+            recipe: None,
+            ingredients: Vec::new(),
+            work_required: kind.work_required(),
+        }
+    }
+}
+
+/// Every piece of designated work in the colony. Idle dwarves claim jobs from here.
+#[derive(Default)]
+pub struct JobBoard {
+    pub jobs: Vec<Job>,
+    next_id: usize,
+}
+
+impl JobBoard {
+    pub fn designate(&mut self, kind: JobKind, x: i32, y: i32) -> Option<usize> {
+        if self.jobs.iter().any(|job| job.kind == kind && job.x == x && job.y == y) {
+            return None;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.jobs.push(Job::new(id, kind, x, y));
+        Some(id)
+    }
+
+    pub fn post_haul(&mut self, item: Entity, from: (i32, i32), to: (i32, i32)) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut job = Job::new(id, JobKind::Haul, from.0, from.1);
+        job.item = Some(item);
+        job.destination = Some(to);
+
+        self.jobs.push(job);
+        id
+    }
+
+    pub fn post_craft(&mut self, workshop: Entity, recipe: usize, at: (i32, i32), ingredients: Vec<Entity>, turns: i32) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut job = Job::new(id, JobKind::Craft, at.0, at.1);
+        job.target = Some(workshop);
+        job.recipe = Some(recipe);
+        job.ingredients = ingredients;
+        job.work_required = turns;
+
+        self.jobs.push(job);
+        id
+    }
+
+    pub fn post_build(&mut self, target: Entity, at: (i32, i32), ingredients: Vec<Entity>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut job = Job::new(id, JobKind::Build, at.0, at.1);
+        job.target = Some(target);
+        job.ingredients = ingredients;
+
+        self.jobs.push(job);
+        id
+    }
+
+    /// Items some job already has plans for, either hauling them somewhere or using them up.
+    pub fn reserved_items(&self) -> Vec<Entity> {
+        let mut reserved = Vec::new();
+
+        for job in self.jobs.iter() {
+            reserved.extend(job.item.iter());
+            reserved.extend(job.ingredients.iter());
+        }
+
+        reserved
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn release(&mut self, id: usize) {
+        if let Some(job) = self.get_mut(id) {
+            job.worker = None;
+        }
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        self.jobs.retain(|job| job.id != id);
+    }
+}
+
+/// Picks the nearest unreserved items satisfying a list of (name, count) requirements.
+/// `candidates` are the loose items to choose from as (entity, name, x, y).
+/// Returns `None` unless every requirement can be met.
+pub fn find_ingredients(requirements: &[(String, usize)], near: (i32, i32), reserved: &[Entity], candidates: &[(Entity, String, i32, i32)]) -> Option<Vec<(Entity, i32, i32)>> {
+    let mut chosen: Vec<(Entity, i32, i32)> = Vec::new();
+
+    for (name, count) in requirements.iter() {
+        for _ in 0..*count {
+            let nearest = candidates.iter()
+                .filter(|(item, item_name, _, _)| item_name == name && !reserved.contains(item) && !chosen.iter().any(|(c, _, _)| c == item))
+                .min_by_key(|(_, _, x, y)| (x - near.0).abs() + (y - near.1).abs());
+
+            match nearest {
+                Some((item, _, x, y)) => chosen.push((*item, *x, *y)),
+                None => return None
+            }
+        }
+    }
+
+    Some(chosen)
+}
+
+pub fn at_job_site(x: i32, y: i32, job_x: i32, job_y: i32) -> bool {
+    rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(x, y), rltk::Point::new(job_x, job_y)) < 1.5
+}
+
+/// Digging leaves behind what the tile was made of: the ore or gems of a deposit, otherwise a stone of the
+/// surrounding rock. Soil just crumbles away. Skilled miners sometimes get two out of a deposit.
+fn spawn_dig_yield(raws: &raws::Raws, lazy: &LazyUpdate, entities: &Entities, x: i32, y: i32, rock: &str, deposit: Option<geology::Deposit>, mining: i32) {
+    let mut rng = rand::thread_rng();
+
+    let (name, material, count) = match deposit {
+        Some(deposit) => {
+            messages::post(format!("Struck {}", deposit.material.to_lowercase()));
+            (deposit.item, raws.material(deposit.material), if rng.gen_range(0, 20) < mining { 2 } else { 1 })
+        }
+        None if geology::layer_of(rock).map_or(true, |layer| layer.yields_stone()) => ("Stone", raws.material(rock), 1),
+        None => return
+    };
+
+    if let Some(template) = raws.item(name) {
+        let material = material.or_else(|| template.material.as_ref());
+
+        for _ in 0..count {
+            raws::build_item_of(lazy.create_entity(entities), template, material, x, y);
+        }
+    }
+}
+
+/// Frees up jobs whose worker has died or has otherwise stopped working on them.
+pub struct JobReleaseSystem {}
+
+impl<'a> System<'a> for JobReleaseSystem {
+    type SystemData = ( WriteExpect<'a, JobBoard>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::AssignedJob>,
+                        WriteStorage<'a, components::CarriedBy>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut board, entities, assigned, mut carried) = data;
+        let mut abandoned: Vec<usize> = Vec::new();
+
+        for job in board.jobs.iter_mut() {
+            if let Some(worker) = job.worker {
+                let still_working = entities.is_alive(worker) && assigned.get(worker).map_or(false, |a| a.job_id == job.id);
+
+                if !still_working {
+                    job.worker = None;
+
+                    // A half-finished haul leaves the item where it was dropped, the hauling system will post a new job for it
+                    if job.kind == JobKind::Haul && job.destination.is_none() {
+                        if let Some(item) = job.item {
+                            carried.remove(item);
+                        }
+                        abandoned.push(job.id);
+                    }
+                }
+            }
+        }
+
+        for job_id in abandoned {
+            board.remove(job_id);
+        }
+    }
+}
+
+/// Hands out unclaimed jobs to idle dwarves, nearest reachable job first. Dwarves will go a bit further
+/// for work they are skilled at. The selected unit is under direct control and never picks up new work by itself.
+pub struct JobAssignmentSystem {}
+
+impl<'a> System<'a> for JobAssignmentSystem {
+    type SystemData = ( WriteExpect<'a, map::Map>,
+                        WriteExpect<'a, JobBoard>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        ReadExpect<'a, components::SelectedUnit>,
+                        ReadExpect<'a, raws::Raws>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::Dwarf>,
+                        ReadStorage<'a, components::MoveOrder>,
+                        ReadStorage<'a, components::Seeking>,
+                        ReadStorage<'a, components::Asleep>,
+                        ReadStorage<'a, components::AutoExplore>,
+                        ReadStorage<'a, components::Position>,
+                        ReadStorage<'a, components::CarriedBy>,
+                        ReadStorage<'a, components::Faction>,
+                        ReadStorage<'a, components::Skills>,
+                        WriteStorage<'a, components::AssignedJob>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut map, mut board, scheduler, selected, raws, entities, dwarves, move_orders, seeking, asleep, explorers, positions, carried,
+             factions, skills, mut assigned) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        // Dwarves that are exploring are busy too, and stop exploring on their own
+        let idle: Vec<(Entity, i32, i32)> = (&entities, &dwarves, &positions, !&assigned, !&move_orders, !&seeking, !&asleep, !&explorers).join()
+            .filter(|(entity, _, _, _, _, _, _, _)| selected.entity != Some(*entity))
+            .map(|(entity, _dwarf, pos, _, _, _, _, _)| (entity, pos.x, pos.y))
+            .collect();
+
+        for (entity, x, y) in idle {
+            let mut candidates: Vec<(f32, usize, i32, i32)> = board.jobs.iter()
+                .filter(|job| job.worker.is_none())
+                .filter(|job| job.ingredients.iter().all(|item| {
+                    entities.is_alive(*item) && carried.get(*item).is_none() &&
+                        positions.get(*item).map_or(false, |pos| at_job_site(pos.x, pos.y, job.x, job.y))
+                }))
+                .map(|job| {
+                    let distance = rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(x, y), rltk::Point::new(job.x, job.y));
+                    let level = skills::job_skill(job, &raws).map_or(0, |skill| skills.get(entity).map_or(0, |s| s.level(skill)));
+
+                    (distance - level as f32 * skills::SKILL_PREFERENCE, job.id, job.x, job.y)
+                })
+                .collect();
+            candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+            for (_score, job_id, job_x, job_y) in candidates {
+                let reachable = at_job_site(x, y, job_x, job_y) || map.step_towards_as((x, y), (job_x, job_y), factions.get(entity).map(|f| f.kind)).is_some();
+
+                if reachable {
+                    if let Some(job) = board.get_mut(job_id) {
+                        job.worker = Some(entity);
+                    }
+                    assigned.insert(entity, components::AssignedJob{ job_id }).expect("Unable to assign job");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Moves dwarves to their job sites, works the jobs and applies the result once they are done.
+/// Skilled dwarves work faster and make better things, and everyone gets better with practice.
+pub struct JobWorkSystem {}
+
+impl<'a> System<'a> for JobWorkSystem {
+    type SystemData = ( WriteExpect<'a, map::Map>,
+                        WriteExpect<'a, JobBoard>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        WriteExpect<'a, noise::NoiseQueue>,
+                        ReadExpect<'a, raws::Raws>,
+                        Entities<'a>,
+                        Read<'a, LazyUpdate>,
+                        ReadStorage<'a, components::Name>,
+                        WriteStorage<'a, components::Position>,
+                        WriteStorage<'a, components::Viewshed>,
+                        WriteStorage<'a, components::Renderable>,
+                        WriteStorage<'a, components::AssignedJob>,
+                        WriteStorage<'a, components::CarriedBy>,
+                        WriteStorage<'a, components::Workshop>,
+                        ReadStorage<'a, components::Blueprint>,
+                        WriteStorage<'a, components::Constructed>,
+                        ReadStorage<'a, components::Tile>,
+                        ReadStorage<'a, components::Faction>,
+                        WriteStorage<'a, components::Door>,
+                        WriteStorage<'a, components::Skills>,
+                        ReadStorage<'a, components::MadeOf>,
+                        ReadStorage<'a, components::Weight>,
+                        WriteStorage<'a, components::Wading>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut map, mut board, scheduler, mut noises, raws, entities, lazy, names, mut positions, mut viewsheds, mut renderables,
+             mut assigned, mut carried, mut workshops, blueprints, mut constructed, tiles, factions, mut doors, mut skills,
+             made_of, weights, mut wading) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        let mut finished: Vec<Entity> = Vec::new();
+        // Finished jobs along with the worker's level in the job's skill
+        let mut completed: Vec<(usize, i32)> = Vec::new();
+        let mut abandoned: Vec<usize> = Vec::new();
+
+        let workers: Vec<(Entity, usize)> = (&entities, &assigned).join().map(|(entity, a)| (entity, a.job_id)).collect();
+
+        for (entity, job_id) in workers {
+            let (kind, job_x, job_y, item, destination, skill) = match board.get(job_id) {
+                Some(job) => (job.kind, job.x, job.y, job.item, job.destination, skills::job_skill(job, &raws)),
+                None => {
+                    finished.push(entity);
+                    continue;
+                }
+            };
+
+            let (x, y) = match positions.get(entity) {
+                Some(pos) => (pos.x, pos.y),
+                None => continue
+            };
+            let level = skill.map_or(0, |skill| skills.get(entity).map_or(0, |s| s.level(skill)));
+
+            if at_job_site(x, y, job_x, job_y) {
+                if kind == JobKind::Haul {
+                    let item_pos = item.filter(|item| entities.is_alive(*item)).and_then(|item| positions.get(item)).map(|p| (p.x, p.y));
+
+                    match (item, item_pos, destination) {
+                        (Some(item), Some(item_pos), Some(destination)) if item_pos == (job_x, job_y) => {
+                            // Pick the item up and head for the drop-off tile
+                            carried.insert(item, components::CarriedBy{ owner: entity }).expect("Unable to pick up item");
+                            if let Some(item_pos) = positions.get_mut(item) {
+                                item_pos.x = x;
+                                item_pos.y = y;
+                            }
+                            if let Some(job) = board.get_mut(job_id) {
+                                job.x = destination.0;
+                                job.y = destination.1;
+                                job.destination = None;
+                            }
+                        }
+                        (Some(item), Some(_), None) => {
+                            if let Some(item_pos) = positions.get_mut(item) {
+                                item_pos.x = job_x;
+                                item_pos.y = job_y;
+                            }
+                            carried.remove(item);
+                            completed.push((job_id, level));
+                            finished.push(entity);
+                            skills::train(&mut skills, &names, entity, skills::SkillKind::Hauling, skills::EXPERIENCE_PER_ACTION);
+                        }
+                        _ => {
+                            // The item has been eaten, moved or destroyed in the meantime
+                            abandoned.push(job_id);
+                            finished.push(entity);
+                        }
+                    }
+                    continue;
+                }
+
+                let mut done = false;
+                if let Some(job) = board.get_mut(job_id) {
+                    job.progress += skills::work_rate(level);
+                    done = job.progress >= job.work_required;
+                }
+                if let Some(skill) = skill {
+                    skills::train(&mut skills, &names, entity, skill, skills::EXPERIENCE_PER_ACTION);
+                }
+
+                if kind == JobKind::Dig {
+                    noises.emit(job_x, job_y, noise::DIG_LOUDNESS);
+                }
+
+                if done {
+                    completed.push((job_id, level));
+                    finished.push(entity);
+                }
+                continue;
+            }
+
+            // Heavy loads only let the hauler take a step every other turn
+            let carrying = if destination.is_none() { item } else { None };
+            let heavy = carrying.and_then(|item| weights.get(item)).map_or(false, |w| w.kilograms > materials::HEAVY_LOAD);
+            if heavy && scheduler.turn % 2 == 1 {
+                continue;
+            }
+
+            let faction = factions.get(entity).map(|f| f.kind);
+
+            match map.step_towards_as((x, y), (job_x, job_y), faction) {
+                Some((new_x, new_y)) => {
+                    if !map.can_enter(map.xy_idx(new_x, new_y)) || !doors::try_pass(&mut *map, &mut doors, &mut noises, new_x, new_y, faction) || !fluids::wade(&map, &mut wading, entity, map.xy_idx(new_x, new_y)) {
+                        continue;
+                    }
+
+                    let old_idx = map.xy_idx(x, y);
+                    let new_idx = map.xy_idx(new_x, new_y);
+                    map.blocked[old_idx] = false;
+                    map.blocked[new_idx] = true;
+
+                    if let Some(pos) = positions.get_mut(entity) {
+                        pos.x = new_x;
+                        pos.y = new_y;
+                    }
+                    if let Some(viewshed) = viewsheds.get_mut(entity) {
+                        viewshed.dirty = true;
+                    }
+
+                    // Carried items come along
+                    if let (Some(item), None) = (item, destination) {
+                        if let Some(item_pos) = positions.get_mut(item) {
+                            item_pos.x = new_x;
+                            item_pos.y = new_y;
+                        }
+                    }
+                }
+                None => {
+                    if let Some(name) = names.get(entity) {
+                        messages::post(format!("{} cannot reach the job at {}, {}", name.name, job_x, job_y));
+                    }
+
+                    if let (JobKind::Haul, Some(item), None) = (kind, item, destination) {
+                        carried.remove(item);
+                        abandoned.push(job_id);
+                    } else {
+                        board.release(job_id);
+                    }
+                    finished.push(entity);
+                }
+            }
+        }
+
+        for entity in finished {
+            assigned.remove(entity);
+        }
+
+        for job_id in abandoned {
+            board.remove(job_id);
+        }
+
+        for (job_id, level) in completed {
+            let (kind, x, y, target, recipe, ingredients) = match board.get(job_id) {
+                Some(job) => (job.kind, job.x, job.y, job.target, job.recipe, job.ingredients.clone()),
+                None => continue
+            };
+
+            match kind {
+                JobKind::Dig => {
+                    // Digging through a built wall leaves nothing to take down later, and only gives a plain stone back
+                    let idx = map.xy_idx(x, y);
+                    let built = map.tiles.get(&(x, y)).and_then(|tile| constructed.remove(*tile)).is_some();
+                    let rock = if built { "" } else { map.rock[idx] };
+                    let deposit = map.deposits.remove(&idx);
+
+                    map::set_tile_glyph(&mut *map, &mut renderables, x, y, '.');
+                    fluids::breach(&mut *map, x, y);
+                    spawn_dig_yield(&raws, &lazy, &entities, x, y, rock, deposit, level);
+
+                    for viewshed in (&mut viewsheds).join() {
+                        viewshed.dirty = true;
+                    }
+                }
+                JobKind::Build => {
+                    if let Some(workshop) = target.and_then(|target| workshops.get_mut(target)) {
+                        workshop.built = true;
+                        messages::post(format!("{} is ready for work", workshop.kind.name()));
+                    }
+
+                    if let Some((blueprint_entity, blueprint)) = target.and_then(|target| blueprints.get(target).map(|b| (target, b))) {
+                        let kind = blueprint.kind;
+                        let all_there = ingredients.iter().all(|item| entities.is_alive(*item) && carried.get(*item).is_none());
+
+                        if !all_there {
+                            messages::post(format!("Materials went missing, the {} will have to wait", kind.name()));
+                            board.remove(job_id);
+                            continue;
+                        }
+
+                        // Nobody gets walled in: wait for whoever is standing there to move
+                        if kind == construction::ConstructionKind::Wall && map.blocked[map.xy_idx(x, y)] {
+                            board.release(job_id);
+                            continue;
+                        }
+
+                        let material = ingredients.first().and_then(|item| made_of.get(*item)).and_then(|m| raws.material(&m.material));
+
+                        for item in ingredients.iter() {
+                            entities.delete(*item).expect("Unable to delete");
+                        }
+                        entities.delete(blueprint_entity).expect("Unable to delete");
+
+                        if kind.is_terrain() {
+                            map::set_tile_glyph(&mut *map, &mut renderables, x, y, kind.glyph());
+                            if let Some(tile) = map.tiles.get(&(x, y)) {
+                                constructed.insert(*tile, components::Constructed{ kind }).expect("Unable to insert construction");
+                            }
+                        } else {
+                            let furniture = construction::spawn_furniture(&lazy, &entities, kind, x, y);
+                            lazy.insert(furniture, components::Quality{ tier: skills::roll_quality(level) });
+
+                            if let Some(material) = material {
+                                lazy.insert(furniture, components::MadeOf{ material: material.name.clone() });
+                                lazy.insert(furniture, components::Renderable{ glyph: kind.glyph(), color: material.colour });
+                            }
+                        }
+                        messages::post(format!("{} built", kind.name()));
+
+                        for viewshed in (&mut viewsheds).join() {
+                            viewshed.dirty = true;
+                        }
+                    }
+                }
+                JobKind::Deconstruct => {
+                    let furniture = (&entities, &constructed, &positions, !&tiles).join()
+                        .find(|(_, _, pos, _)| pos.x == x && pos.y == y)
+                        .map(|(entity, built, _, _)| (entity, built.kind));
+
+                    if let Some((entity, kind)) = furniture {
+                        entities.delete(entity).expect("Unable to delete");
+                        construction::return_materials(&raws, &lazy, &entities, kind, x, y);
+                        messages::post(format!("{} taken down", kind.name()));
+                    } else if let Some(tile) = map.tiles.get(&(x, y)).cloned() {
+                        if let Some(built) = constructed.remove(tile) {
+                            map::set_tile_glyph(&mut *map, &mut renderables, x, y, '.');
+                            construction::return_materials(&raws, &lazy, &entities, built.kind, x, y);
+                            messages::post(format!("{} taken down", built.kind.name()));
+
+                            for viewshed in (&mut viewsheds).join() {
+                                viewshed.dirty = true;
+                            }
+                        }
+                    }
+                }
+                JobKind::Craft => {
+                    let all_there = ingredients.iter().all(|item| entities.is_alive(*item) && carried.get(*item).is_none());
+
+                    if let (true, Some(recipe)) = (all_there, recipe) {
+                        let ingredient_material = ingredients.first().and_then(|item| made_of.get(*item)).and_then(|m| raws.material(&m.material));
+
+                        for item in ingredients.iter() {
+                            entities.delete(*item).expect("Unable to delete");
+                        }
+
+                        let recipe = &raws.recipes[recipe];
+                        for (output, count) in recipe.outputs.iter() {
+                            if let Some(template) = raws.item(output) {
+                                let material = materials::crafted_material(template, ingredient_material);
+
+                                for _ in 0..*count {
+                                    raws::build_crafted_item(lazy.create_entity(&entities), template, material, skills::roll_quality(level), x, y);
+                                }
+                            }
+                        }
+                        messages::post(format!("{} completed", recipe.name));
+
+                        if let Some(workshop) = target.and_then(|target| workshops.get_mut(target)) {
+                            if !workshop.queue.is_empty() {
+                                workshop.queue.remove(0);
+                            }
+                            workshop.active_job = None;
+                        }
+                    } else {
+                        messages::post("Ingredients went missing, the workshop will try again".to_string());
+                    }
+                }
+                JobKind::Haul => {}
+            }
+
+            board.remove(job_id);
+        }
+    }
+}
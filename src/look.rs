@@ -0,0 +1,143 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+
+use crate::components;
+use crate::describe_item;
+use crate::geology;
+use crate::input;
+use crate::map;
+use crate::render::Renderer;
+use crate::Game;
+use crate::ScreenLayout;
+
+/// The examine cursor, and where the camera was before looking around so it can go back there afterwards.
+pub struct LookMode {
+    pub x: i32,
+    pub y: i32,
+    camera: (i32, i32, bool)
+}
+
+/// What is under the look cursor: the terrain, and if it is in sight, the creatures and items on it.
+/// Remembered tiles only show their terrain as it was last seen.
+pub fn render_look_panel(renderer: &mut dyn Renderer, ecs: &World, screen_layout: &ScreenLayout, x: i32, y: i32) -> Result<()> {
+    let map = ecs.fetch::<map::Map>();
+    let entities = ecs.entities();
+    let names = ecs.read_storage::<components::Name>();
+    let positions = ecs.read_storage::<components::Position>();
+    let tiles = ecs.read_storage::<components::Tile>();
+    let stats = ecs.read_storage::<components::CombatStats>();
+    let asleep = ecs.read_storage::<components::Asleep>();
+    let items = ecs.read_storage::<components::Item>();
+    let carried = ecs.read_storage::<components::CarriedBy>();
+    let qualities = ecs.read_storage::<components::Quality>();
+    let made_of = ecs.read_storage::<components::MadeOf>();
+
+    let margin = 10.0;
+    let faded = Color::WHITE.with_alpha(0.6);
+    let mut origin = screen_layout.right_panel_origin_pixels + Vector::new(margin, 30.0);
+    let idx = map.xy_idx(x, y);
+
+    renderer.text(&format!("Looking at {}, {}", x, y), origin, 20.0, Color::WHITE)?;
+    origin.y += 30.0;
+
+    renderer.text(&map.describe_tile(idx), origin, 14.0, Color::WHITE)?;
+    origin.y += 18.0;
+    if map.glyph_map[idx] == '#' {
+        if let Some(layer) = geology::layer_of(map.rock[idx]) {
+            renderer.text(&format!("Part of the {}", layer.name()), origin, 14.0, faded)?;
+            origin.y += 18.0;
+        }
+    }
+
+    if !map.visible_map[idx] {
+        origin.y += 10.0;
+        renderer.text("Out of sight", origin, 14.0, faded)?;
+        return Ok(());
+    }
+
+    let here: Vec<Entity> = (&entities, &positions, !&tiles).join()
+        .filter(|(_, pos, _)| pos.x == x && pos.y == y)
+        .map(|(entity, _, _)| entity)
+        .collect();
+
+    let mut creatures = Vec::new();
+    let mut things = Vec::new();
+    for entity in here {
+        if let Some(stat) = stats.get(entity) {
+            let mut description = format!("{} ({}/{} hp)", names.get(entity).map_or("Someone", |n| n.name.as_str()), stat.hp, stat.max_hp);
+            if asleep.get(entity).is_some() {
+                description.push_str(", asleep");
+            }
+            creatures.push(description);
+        } else if items.get(entity).is_some() {
+            let mut description = describe_item(entity, &names, &qualities, &made_of);
+            if let Some(owner) = carried.get(entity).and_then(|c| names.get(c.owner)) {
+                description.push_str(&format!(", carried by {}", owner.name));
+            }
+            things.push(description);
+        } else if let Some(name) = names.get(entity) {
+            things.push(name.name.clone());
+        }
+    }
+
+    for (heading, lines) in [("Creatures", creatures), ("Things", things)].iter() {
+        if lines.is_empty() {
+            continue;
+        }
+
+        origin.y += 10.0;
+        renderer.text(heading, origin, 14.0, Color::YELLOW)?;
+        origin.y += 18.0;
+        for line in lines.iter() {
+            renderer.text(line, origin, 14.0, Color::WHITE)?;
+            origin.y += 18.0;
+        }
+    }
+
+    Ok(())
+}
+
+/// Starts looking around from the selected unit, or stops and puts the camera back where it was.
+pub fn toggle_look(game: &mut Game) {
+    let mut camera = game.ecs.write_resource::<components::Camera>();
+
+    match game.look.take() {
+        Some(look) => {
+            let (x, y, follow_selected) = look.camera;
+            camera.x = x;
+            camera.y = y;
+            camera.follow_selected = follow_selected;
+        }
+        None => {
+            let player_pos = game.ecs.fetch::<components::PlayerPosition>();
+            game.look = Some(LookMode { x: player_pos.x, y: player_pos.y, camera: (camera.x, camera.y, camera.follow_selected) });
+            camera.x = player_pos.x;
+            camera.y = player_pos.y;
+            camera.follow_selected = false;
+        }
+    }
+}
+
+/// Movement keys move the look cursor over tiles that have been seen, keeping it in the middle of the view.
+pub fn look_input(game: &mut Game) {
+    let actions = game.ecs.write_resource::<input::InputQueue>().drain();
+    let look = match game.look.as_mut() {
+        Some(look) => look,
+        None => return
+    };
+
+    let map = game.ecs.fetch::<map::Map>();
+    let mut camera = game.ecs.write_resource::<components::Camera>();
+
+    for (delta_x, delta_y) in actions.iter().filter_map(|action| action.direction()) {
+        let (x, y) = (look.x + delta_x, look.y + delta_y);
+
+        if map.in_bounds(x, y) && map.revealed_map[map.xy_idx(x, y)] {
+            look.x = x;
+            look.y = y;
+        }
+    }
+
+    camera.x = look.x;
+    camera.y = look.y;
+}
@@ -1,27 +1,65 @@
 use quicksilver::prelude::*;
+use quicksilver::graphics::View;
 use specs::prelude::*;
 use specs::{Builder, World};
 use rltk::{ Algorithm2D };
 
-use std::collections::HashMap;
+use rand::Rng;
+
 use std::cmp;
 
 pub mod map;
 pub mod components;
 pub mod systems;
+pub mod factions;
+pub mod scheduler;
+pub mod noise;
+pub mod jobs;
+pub mod needs;
+pub mod stockpiles;
+pub mod raws;
+pub mod workshops;
+pub mod construction;
+pub mod doors;
+pub mod fluids;
+pub mod heat;
+pub mod calendar;
+pub mod spawner;
+pub mod skills;
+pub mod materials;
+pub mod geology;
+pub mod input;
+pub mod ranged;
+pub mod tileset;
+pub mod render;
+pub mod terminal;
+pub mod messages;
+pub mod mouse;
+pub mod look;
+pub mod minimap;
+pub mod camera;
+
+use render::Renderer;
 
 static TILE_EDGE_PIXELS: i32 = 24;
+/// Size the window opens at. It can be resized from there.
 static WINDOW_WIDTH_TILES: i32 = 49;
 static WINDOW_HEIGHT_TILES: i32 = 27;
-static SCREEN_WIDTH_TILES: i32 = 41;
-static SCREEN_HEIGHT_TILES: i32 = 23;
+/// However small the window gets, the map view keeps at least this many tiles each way.
+static MIN_SCREEN_TILES: i32 = 9;
+/// Panel sizes used when there is no `layout.txt` in the working directory.
+static DEFAULT_LAYOUT_RAW: &str = include_str!("../static/layout.txt");
+/// Players can override the panel sizes with a file of the same format in the working directory.
+static LAYOUT_FILE: &str = "layout.txt";
 static SCREEN_ORIGIN_X_TILES: i32 = 0;
 static SCREEN_ORIGIN_Y_TILES: i32 = 0;
+/// Frames between drawing the screen when playing in a terminal, to keep down how much is sent over a connection.
+static TERMINAL_DRAW_FRAMES: i32 = 4;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum RunState { Paused, Running }
 
-struct ScreenLayout {
+pub struct ScreenLayout {
     tile_size_pixels: Vector,
     window_size: Vector,
     screen_size: Vector,
@@ -47,10 +85,62 @@ struct ScreenLayout {
     top_panel_size_pixels: Vector,
     bottom_panel_size_pixels: Vector,
 
-    left_margin: f32,
-    right_margin: f32,
-    top_margin: f32,
-    bottom_margin: f32,
+}
+
+/// How much of the window the panels around the map view take up, in tiles.
+struct PanelSizes {
+    right: i32,
+    bottom: i32,
+    /// Text takes up more of a terminal than it does of the window, so the panels get sizes of their own there.
+    terminal_right: i32,
+    terminal_bottom: i32
+}
+
+impl PanelSizes {
+    /// Reads `layout.txt` from the working directory. Without one, or if it has a mistake in it, the default
+    /// sizes are used instead.
+    fn load() -> PanelSizes {
+        let raw = match std::fs::read_to_string(LAYOUT_FILE) {
+            Ok(raw) => raw,
+            Err(_) => return PanelSizes::defaults()
+        };
+
+        PanelSizes::parse(&raw).unwrap_or_else(|error| {
+            messages::post(format!("Could not use {}: {}. Using the default panel sizes instead.", LAYOUT_FILE, error));
+            PanelSizes::defaults()
+        })
+    }
+
+    fn defaults() -> PanelSizes {
+        PanelSizes::parse(DEFAULT_LAYOUT_RAW).expect("The default panel sizes should parse")
+    }
+
+    /// Parses a layout file, returning what is wrong with the first line that can't be used.
+    fn parse(raw: &str) -> std::result::Result<PanelSizes, String> {
+        let mut sizes = PanelSizes { right: 8, bottom: 4, terminal_right: 28, terminal_bottom: 3 };
+
+        for line in raw.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let fields: Vec<&str> = line.split('|').map(|field| field.trim()).collect();
+            let tiles = match fields.get(1).map(|tiles| tiles.parse::<i32>()) {
+                Some(Ok(tiles)) if tiles >= 0 => tiles,
+                _ => return Err(format!("panel sizes need a whole number of tiles: '{}'", line))
+            };
+
+            match fields[0] {
+                "right_panel" => sizes.right = tiles,
+                "bottom_panel" => sizes.bottom = tiles,
+                "terminal_right_panel" => sizes.terminal_right = tiles,
+                "terminal_bottom_panel" => sizes.terminal_bottom = tiles,
+                other => return Err(format!("unknown panel '{}'", other))
+            }
+        }
+
+        Ok(sizes)
+    }
+
+    fn for_terminal(&self) -> PanelSizes {
+        PanelSizes { right: self.terminal_right, bottom: self.terminal_bottom, terminal_right: self.terminal_right, terminal_bottom: self.terminal_bottom }
+    }
 }
 
 impl ScreenLayout {
@@ -66,11 +156,28 @@ impl ScreenLayout {
         self.bottom_panel_size_pixels = self.bottom_panel_size.times(self.tile_size_pixels);
     }
 
+    /// How many whole map tiles fit in the map view at a zoom level.
+    fn view_size(&self, zoom: f32) -> Vector {
+        Vector::new((self.screen_size.x / zoom).floor(), (self.screen_size.y / zoom).floor())
+    }
+
+    /// Lays out a window of any size: the panels get the room they are configured to take and the map view
+    /// gets the rest. The window counts as big enough for the smallest map view, even if it isn't.
+    fn fit(tile_size_pixels: Vector, window_pixels: Vector, panels: &PanelSizes) -> ScreenLayout {
+        let screen_origin = Vector::new(SCREEN_ORIGIN_X_TILES, SCREEN_ORIGIN_Y_TILES);
+        let window_size = Vector::new((window_pixels.x / tile_size_pixels.x).floor(), (window_pixels.y / tile_size_pixels.y).floor());
+
+        let screen_size = Vector::new((window_size.x - screen_origin.x - panels.right as f32).max(MIN_SCREEN_TILES as f32),
+                                      (window_size.y - screen_origin.y - panels.bottom as f32).max(MIN_SCREEN_TILES as f32));
+        let window_size = Vector::new(window_size.x.max(screen_origin.x + screen_size.x + panels.right as f32),
+                                      window_size.y.max(screen_origin.y + screen_size.y + panels.bottom as f32));
+
+        let mut screen_layout = ScreenLayout::new(tile_size_pixels, window_size, screen_size, screen_origin);
+        screen_layout.set_pixel_sizes();
+        screen_layout
+    }
+
     fn new(tile_size_pixels: Vector, window_size: Vector, screen_size: Vector, screen_origin: Vector) -> ScreenLayout {
-        if window_size.x % 2.0 != 1.0 || window_size.y % 2.0 != 1.0 || screen_size.x % 2.0 != 1.0 || screen_size.y % 2.0 != 1.0 {
-            panic!("Must use odd screen and window dimensions.");
-        }
-        
         ScreenLayout {
             tile_size_pixels,
             window_size,
@@ -90,141 +197,402 @@ impl ScreenLayout {
             left_panel_size: Vector::new(screen_origin.x, window_size.y),
             right_panel_size: Vector::new(window_size.x - screen_origin.x - screen_size.x, window_size.y),
             top_panel_size: Vector::new(screen_size.x - 2.0 * screen_origin.x, screen_origin.y),
-            bottom_panel_size: Vector::new(screen_size.x - 2.0 * screen_origin.x, screen_origin.y + screen_size.y),
+            bottom_panel_size: Vector::new(screen_size.x - 2.0 * screen_origin.x, window_size.y - screen_origin.y - screen_size.y),
 
             left_panel_size_pixels: Vector::new(0, 0), 
             right_panel_size_pixels: Vector::new(0, 0),
             top_panel_size_pixels: Vector::new(0, 0),
             bottom_panel_size_pixels: Vector::new(0, 0),
-
-            left_margin: screen_origin.x,
-            right_margin: window_size.x - screen_origin.x - screen_size.x,
-            top_margin: screen_origin.y,
-            bottom_margin: window_size.y - screen_origin.y - screen_size.y,
         }
     }
 }
 
-struct Game {
-    tileset: Asset<HashMap<char, Image>>,
+/// Aiming a ranged weapon at one of the hostiles the shooter can see, nearest first.
+struct Targeting {
+    shooter: Entity,
+    targets: Vec<Entity>,
+    index: usize
+}
+
+pub struct Game {
+    /// Every tileset in `static/tilesets.txt`, loaded up front so switching between them is instant.
+    tilesets: Vec<Asset<tileset::Tileset>>,
+    tileset_index: usize,
     screen_layout: ScreenLayout,
+    panels: PanelSizes,
+    /// The window size the layout was last fitted to, in pixels.
+    window_pixels: Vector,
     ecs: World,
-    runstate: RunState
+    runstate: RunState,
+    workshop_kind: usize,
+    /// Index into `CONSTRUCTION_KINDS` of what gets placed in build mode, `None` outside build mode.
+    build_mode: Option<usize>,
+    show_unit_info: bool,
+    show_inventory: bool,
+    context_menu: Option<mouse::ContextMenu>,
+    /// While looking around the game is frozen and movement keys move the cursor instead.
+    look: Option<look::LookMode>,
+    /// Like looking, aiming freezes the game.
+    targeting: Option<Targeting>,
+    /// Somewhere the player wants to be able to get back to.
+    marked: Option<(i32, i32)>,
+    /// Frames until edge-scrolling takes its next step.
+    edge_scroll_timer: i32
 }
 
 fn generate_entities(ecs: &mut World) {
-    ecs
-    .create_entity()
-    .with(components::Position { x: 40, y: 25 })
-    .with(components::Renderable {
-        glyph: '@',
-        color: Color::BLACK,
-    })
-    .with(components::Player{})
-    .with(components::Viewshed{ visible_tiles : Vec::new(), range : 8, dirty: true })
-    .with(components::BlocksTile{})
-    .build();
+    spawner::dwarf(ecs.create_entity(), "Urist", '@', 40, 25);
 
     ecs.insert(components::PlayerPosition { x: 40, y: 25 });
+    ecs.insert(components::SelectedUnit { entity: None });
+    ecs.insert(components::Camera { x: 40, y: 25, follow_selected: true, zoom_level: camera::DEFAULT_ZOOM_LEVEL });
 
-    ecs
-    .create_entity()
-    .with(components::Position { x: 30, y: 10 })
-    .with(components::Renderable {
-        glyph: 'g',
-        color: Color::GREEN,
-    })
-    .with(components::Monster{})
-    .with(components::Viewshed{ visible_tiles : Vec::new(), range : 8, dirty: true })
-    .with(components::Name{ name: "Protogoblin".to_string() })
-    .with(components::BlocksTile{})
-    .build();
-}
-
-fn render_text(window: &mut Window, text: &'static str, position: Vector, font_size: f32, colour: Color) -> Result<()> {
-    let mut to_draw = Asset::new(Font::load("Cascadia.ttf").and_then(move |font| {
-        font.render(
-            text,
-            &FontStyle::new(font_size, colour),
-        )
-    }));
-
-    to_draw.execute(|image| {
-        window.draw(
-            &image
-                .area()
-                .translate((position.x, position.y)),
-            Img(&image),
-        );
-        Ok(())
-    })?;
+    for (name, x) in [("Kadol", 38), ("Dodok", 42)].iter() {
+        spawner::dwarf(ecs.create_entity(), name, 'd', *x, 25);
+    }
 
-    Ok(())
+    spawner::goblin(ecs.create_entity(), 30, 10);
+    spawner::hare(ecs.create_entity(), 20, 30);
+    spawner::merchant(ecs.create_entity(), 45, 20);
+}
+
+/// Scatters food, drink and beds over the floor around the starting position.
+fn generate_supplies(ecs: &mut World) {
+    let mut rng = rand::thread_rng();
+    let mut floor: Vec<(i32, i32)> = Vec::new();
+
+    {
+        let map = ecs.fetch::<map::Map>();
+        let renderables = ecs.read_storage::<components::Renderable>();
+
+        for x in 32..=48 {
+            for y in 17..=33 {
+                if let Some(tile) = map.tiles.get(&(x, y)) {
+                    if renderables.get(*tile).map_or(false, |r| r.glyph == '.') {
+                        floor.push((x, y));
+                    }
+                }
+            }
+        }
+    }
+
+    let supplies: Vec<Option<raws::ItemTemplate>> = {
+        let raws = ecs.fetch::<raws::Raws>();
+        ["Plump helmet", "Dwarven ale", "Log"].iter().map(|name| raws.item(name).cloned()).collect()
+    };
+
+    for supply in 0..12 {
+        if floor.is_empty() {
+            return;
+        }
+        let (x, y) = floor.remove(rng.gen_range(0, floor.len()));
+
+        // Every fourth supply is a bed, the rest come from the item templates
+        if supply % 4 == 3 {
+            ecs.create_entity()
+                .with(components::Position { x, y })
+                .with(components::Renderable { glyph: '_', color: Color::from_rgba(120, 80, 40, 1.0) })
+                .with(components::Name{ name: "Bed".to_string() })
+                .with(components::Bed{})
+                .with(components::Temperature{ degrees: heat::AMBIENT_TEMPERATURE })
+                .with(components::Flammable{ ignition: heat::WOOD_IGNITION, fuel: heat::WOOD_FUEL })
+                .build();
+        } else if let Some(template) = &supplies[supply % 4] {
+            raws::build_item(ecs.create_entity(), template, x, y);
+        }
+    }
 }
 
-fn render_bar(window: &mut Window, colour: Color, current_value: f32, origin: Vector, width: f32, height: f32) -> Result<()> {
+fn render_bar(renderer: &mut dyn Renderer, colour: Color, current_value: f32, origin: Vector, width: f32, height: f32) -> Result<()> {
     // Full health
-    window.draw(
-        &Rectangle::new(origin, (width, height)),
-        Col(colour.with_alpha(0.5)),
-    );
+    renderer.fill(Rectangle::new(origin, (width, height)), colour.with_alpha(0.5))?;
 
     // Current health
-    window.draw(
-        &Rectangle::new(origin, (current_value, height)),
-        Col(colour),
-    );
+    renderer.fill(Rectangle::new(origin, (current_value, height)), colour)
+}
+
+/// Needs bars and status effects of the selected dwarf, drawn into the right-hand panel.
+fn render_unit_panel(renderer: &mut dyn Renderer, ecs: &World, screen_layout: &ScreenLayout) -> Result<()> {
+    let selected = ecs.fetch::<components::SelectedUnit>();
+    let entity = match selected.entity {
+        Some(entity) => entity,
+        None => return Ok(())
+    };
+
+    let names = ecs.read_storage::<components::Name>();
+    let hungers = ecs.read_storage::<components::Hunger>();
+    let thirsts = ecs.read_storage::<components::Thirst>();
+    let fatigues = ecs.read_storage::<components::Fatigue>();
+    let asleep = ecs.read_storage::<components::Asleep>();
+
+    let margin = 10.0;
+    let bar_width = screen_layout.right_panel_size_pixels.x - 2.0 * margin;
+    let mut origin = screen_layout.right_panel_origin_pixels + Vector::new(margin, 30.0);
+    let mut statuses: Vec<&str> = Vec::new();
+
+    if let Some(name) = names.get(entity) {
+        renderer.text(&name.name, origin, 20.0, Color::WHITE)?;
+        origin.y += 30.0;
+    }
+
+    let mut needs_bars: Vec<(&str, Color, components::NeedKind, i32, i32)> = Vec::new();
+    if let Some(hunger) = hungers.get(entity) {
+        needs_bars.push(("Hunger", Color::ORANGE, components::NeedKind::Hunger, hunger.current, hunger.max));
+    }
+    if let Some(thirst) = thirsts.get(entity) {
+        needs_bars.push(("Thirst", Color::CYAN, components::NeedKind::Thirst, thirst.current, thirst.max));
+    }
+    if let Some(fatigue) = fatigues.get(entity) {
+        needs_bars.push(("Rest", Color::PURPLE, components::NeedKind::Fatigue, fatigue.current, fatigue.max));
+    }
+
+    for (label, colour, need, current, max) in needs_bars {
+        renderer.text(label, origin, 14.0, Color::WHITE)?;
+        let fraction = current as f32 / max as f32;
+        render_bar(renderer, colour, fraction * bar_width, origin + Vector::new(0.0, 18.0), bar_width, 8.0)?;
+        origin.y += 36.0;
+
+        let status = needs::status(need, current, max);
+        if !status.is_empty() {
+            statuses.push(status);
+        }
+    }
+
+    if asleep.get(entity).is_some() {
+        statuses.push("Asleep");
+    }
+
+    for status in statuses {
+        renderer.text(status, origin, 14.0, Color::RED)?;
+        origin.y += 18.0;
+    }
 
     Ok(())
 }
 
-fn camera_translate(player_position: Vector, object_position: Vector, map_size: Vector, screen_layout: &ScreenLayout) -> Vector {
-    // the new origin is the player position + half the screen dimensions + (1, 1) to get player in the middle
-    let half_screen_edges = (screen_layout.screen_size - Vector::new(1.0, 1.0)).times(Vector::new(0.5, 0.5));
-    let mut player_position_to_use = player_position; 
+/// The weapon being aimed, the current target and the chance of hitting it.
+fn render_targeting_panel(renderer: &mut dyn Renderer, ecs: &World, screen_layout: &ScreenLayout, targeting: &Targeting) -> Result<()> {
+    let entities = ecs.entities();
+    let names = ecs.read_storage::<components::Name>();
+    let positions = ecs.read_storage::<components::Position>();
+    let stats = ecs.read_storage::<components::CombatStats>();
+    let skills = ecs.read_storage::<components::Skills>();
+    let carried = ecs.read_storage::<components::CarriedBy>();
+    let ranged = ecs.read_storage::<components::Ranged>();
+
+    let margin = 10.0;
+    let mut origin = screen_layout.right_panel_origin_pixels + Vector::new(margin, 30.0);
+    let target = targeting.targets[targeting.index];
+
+    let weapon = ranged::ranged_weapon(targeting.shooter, &entities, &carried, &ranged).and_then(|weapon| names.get(weapon));
+    renderer.text(&format!("Aiming {}", weapon.map_or("", |n| n.name.as_str()).to_lowercase()), origin, 20.0, Color::WHITE)?;
+    origin.y += 30.0;
+
+    let mut description = names.get(target).map_or("Someone".to_string(), |n| n.name.clone());
+    if let Some(stat) = stats.get(target) {
+        description.push_str(&format!(" ({}/{} hp)", stat.hp, stat.max_hp));
+    }
+    renderer.text(&description, origin, 14.0, Color::WHITE)?;
+    origin.y += 18.0;
+
+    if let (Some(from), Some(to)) = (positions.get(targeting.shooter), positions.get(target)) {
+        let distance = i32::max((to.x - from.x).abs(), (to.y - from.y).abs());
+        let combat = skills.get(targeting.shooter).map_or(0, |s| s.level(skills::SkillKind::Combat));
+        renderer.text(&format!("{}% to hit", ranged::hit_chance(distance, combat)), origin, 14.0, Color::WHITE)?;
+        origin.y += 18.0;
+    }
 
-    if player_position.x < half_screen_edges.x {
-        player_position_to_use.x = half_screen_edges.x;
-    } 
-    
-    if player_position.y < half_screen_edges.y {
-        player_position_to_use.y = half_screen_edges.y;
-    } 
+    renderer.text(&format!("Target {} of {}", targeting.index + 1, targeting.targets.len()), origin, 14.0, Color::WHITE.with_alpha(0.6))?;
+
+    Ok(())
+}
+
+/// A full overview of the selected unit over the map view: combat stats, current job and skills.
+fn render_unit_info(renderer: &mut dyn Renderer, ecs: &World, screen_layout: &ScreenLayout) -> Result<()> {
+    let selected = ecs.fetch::<components::SelectedUnit>();
+    let entity = match selected.entity {
+        Some(entity) => entity,
+        None => return Ok(())
+    };
+
+    let names = ecs.read_storage::<components::Name>();
+    let combat_stats = ecs.read_storage::<components::CombatStats>();
+    let assigned = ecs.read_storage::<components::AssignedJob>();
+    let unit_skills = ecs.read_storage::<components::Skills>();
+    let board = ecs.fetch::<jobs::JobBoard>();
+
+    let area = Vector::new(screen_layout.right_panel_origin_pixels.x, screen_layout.bottom_panel_origin_pixels.y);
+    renderer.fill(Rectangle::new(Vector::new(0.0, 0.0), area), Color::BLACK.with_alpha(0.85))?;
+
+    let margin = 20.0;
+    let bar_width = 200.0;
+    let mut origin = Vector::new(margin, margin);
+
+    if let Some(name) = names.get(entity) {
+        renderer.text(&name.name, origin, 24.0, Color::WHITE)?;
+        origin.y += 36.0;
+    }
+
+    if let Some(stats) = combat_stats.get(entity) {
+        let text = format!("HP {}/{}   Power {}   Defense {}", stats.hp, stats.max_hp, stats.power, stats.defense);
+        renderer.text(&text, origin, 14.0, Color::WHITE)?;
+        origin.y += 24.0;
+    }
+
+    let job = assigned.get(entity).and_then(|a| board.get(a.job_id)).map_or("Idle".to_string(), |job| format!("{:?}", job.kind));
+    renderer.text(&format!("Job: {}", job), origin, 14.0, Color::WHITE)?;
+    origin.y += 36.0;
 
-    if player_position.x > map_size.x - half_screen_edges.x - 1.0 {
-        player_position_to_use.x = map_size.x - half_screen_edges.x - 1.0;
-    } 
+    if let Some(unit_skills) = unit_skills.get(entity) {
+        for kind in skills::SKILL_KINDS.iter() {
+            let experience = *unit_skills.experience.get(kind).unwrap_or(&0);
+            let level = skills::level_for(experience);
 
-    if player_position.y > map_size.y - half_screen_edges.y - 1.0 {
-        player_position_to_use.y = map_size.y - half_screen_edges.y - 1.0;
-    } 
+            let text = format!("{}: {} ({})", kind.name(), skills::level_name(level), level);
+            renderer.text(&text, origin, 14.0, Color::WHITE)?;
 
-    let translation = player_position_to_use - half_screen_edges; 
-    let new_position = object_position - translation;
-    new_position
+            // Progress towards the next level
+            let floor = level * level * skills::EXPERIENCE_PER_LEVEL;
+            let ceiling = (level + 1) * (level + 1) * skills::EXPERIENCE_PER_LEVEL;
+            let fraction = if level >= skills::MAX_SKILL_LEVEL { 1.0 } else { (experience - floor) as f32 / (ceiling - floor) as f32 };
+            render_bar(renderer, Color::YELLOW, fraction * bar_width, origin + Vector::new(0.0, 18.0), bar_width, 6.0)?;
+            origin.y += 34.0;
+        }
+    }
+
+    Ok(())
 }
 
-fn should_render(mapped_position: Vector, screen_layout: &ScreenLayout) -> bool {
-    if mapped_position.x < screen_layout.left_margin ||
-    mapped_position.y < screen_layout.top_margin ||
-    mapped_position.x > screen_layout.window_size.x - screen_layout.right_margin - 1.0 ||
-    mapped_position.y > screen_layout.window_size.y - screen_layout.bottom_margin - 1.0 {
-        return false;
+/// An item's name along with what it is made of and how well, e.g. "Fine granite mug".
+fn describe_item(item: Entity, names: &ReadStorage<components::Name>, qualities: &ReadStorage<components::Quality>,
+                 made_of: &ReadStorage<components::MadeOf>) -> String {
+    let mut description = String::new();
+    if let Some(quality) = qualities.get(item) {
+        description.push_str(&format!("{} ", quality.tier.name()));
+    }
+    if let Some(material) = made_of.get(item) {
+        description.push_str(&format!("{} ", material.material.to_lowercase()));
     }
+    description.push_str(&names.get(item).map_or("item".to_string(), |n| n.name.to_lowercase()));
 
-    true
+    description
+}
+
+/// What the selected unit is carrying, over the map view.
+fn render_inventory(renderer: &mut dyn Renderer, ecs: &World, screen_layout: &ScreenLayout) -> Result<()> {
+    let selected = ecs.fetch::<components::SelectedUnit>();
+    let entity = match selected.entity {
+        Some(entity) => entity,
+        None => return Ok(())
+    };
+
+    let entities = ecs.entities();
+    let names = ecs.read_storage::<components::Name>();
+    let carried = ecs.read_storage::<components::CarriedBy>();
+    let made_of = ecs.read_storage::<components::MadeOf>();
+    let qualities = ecs.read_storage::<components::Quality>();
+    let weights = ecs.read_storage::<components::Weight>();
+    let values = ecs.read_storage::<components::Value>();
+    let weapons = ecs.read_storage::<components::Weapon>();
+    let armour = ecs.read_storage::<components::Armour>();
+
+    let area = Vector::new(screen_layout.right_panel_origin_pixels.x, screen_layout.bottom_panel_origin_pixels.y);
+    renderer.fill(Rectangle::new(Vector::new(0.0, 0.0), area), Color::BLACK.with_alpha(0.85))?;
+
+    let mut origin = Vector::new(20.0, 20.0);
+    let title = format!("{} is carrying", names.get(entity).map_or("Unit", |n| n.name.as_str()));
+    renderer.text(&title, origin, 24.0, Color::WHITE)?;
+    origin.y += 36.0;
+
+    let mut total_weight = 0.0;
+    let mut count = 0;
+    for (item, _) in (&entities, &carried).join().filter(|(_, c)| c.owner == entity) {
+        let mut description = describe_item(item, &names, &qualities, &made_of);
+
+        let weight = weights.get(item).map_or(0.0, |w| w.kilograms);
+        total_weight += weight;
+        count += 1;
+        description.push_str(&format!(", {:.1} kg, worth {}", weight, values.get(item).map_or(0, |v| v.coins)));
+        if let Some(weapon) = weapons.get(item) {
+            description.push_str(&format!(", damage {}", weapon.damage));
+        }
+        if let Some(armour) = armour.get(item) {
+            description.push_str(&format!(", defense {}", armour.defense));
+        }
+
+        renderer.text(&description, origin, 14.0, Color::WHITE)?;
+        origin.y += 20.0;
+    }
+
+    if count == 0 {
+        renderer.text("Nothing", origin, 14.0, Color::WHITE)?;
+    } else {
+        origin.y += 10.0;
+        renderer.text(&format!("Total: {:.1} kg", total_weight), origin, 14.0, Color::WHITE)?;
+    }
+
+    Ok(())
 }
 
 fn try_move_player(delta_x: i32, delta_y: i32, ecs: &World) {
+    let entities = ecs.entities();
     let mut positions = ecs.write_storage::<components::Position>();
     let mut players = ecs.write_storage::<components::Player>();
     let mut viewsheds = ecs.write_storage::<components::Viewshed>();
-    let map = ecs.fetch::<map::Map>();
+    let mut move_orders = ecs.write_storage::<components::MoveOrder>();
+    let mut assigned_jobs = ecs.write_storage::<components::AssignedJob>();
+    let mut seeking = ecs.write_storage::<components::Seeking>();
+    let mut explorers = ecs.write_storage::<components::AutoExplore>();
+    let asleep = ecs.read_storage::<components::Asleep>();
+    let selected = ecs.fetch::<components::SelectedUnit>();
+    let factions = ecs.read_storage::<components::Faction>();
+    let combat_stats = ecs.read_storage::<components::CombatStats>();
+    let mut wants_melee = ecs.write_storage::<components::WantsToMelee>();
+    let faction_table = ecs.fetch::<factions::FactionTable>();
+    let mut doors = ecs.write_storage::<components::Door>();
+    let mut wading = ecs.write_storage::<components::Wading>();
+    let mut noises = ecs.write_resource::<noise::NoiseQueue>();
+    let mut map = ecs.fetch_mut::<map::Map>();
+
+    // Bumping into something we are hostile towards attacks it instead of moving
+    let occupants: Vec<(Entity, i32, i32, factions::FactionKind)> = (&entities, &positions, &factions, &combat_stats).join()
+        .map(|(entity, pos, faction, _stats)| (entity, pos.x, pos.y, faction.kind))
+        .collect();
+
+    for (entity, _player, pos, viewshed) in (&entities, &mut players, &mut positions, &mut viewsheds).join() {
+        if selected.entity != Some(entity) || asleep.get(entity).is_some() {
+            continue;
+        }
+
+        // Taking direct control overrides whatever the unit was doing
+        move_orders.remove(entity);
+        assigned_jobs.remove(entity);
+        seeking.remove(entity);
+        explorers.remove(entity);
+        ecs.write_resource::<components::Camera>().follow_selected = true;
+
+        let target = occupants.iter().find(|(_, x, y, _)| *x == pos.x + delta_x && *y == pos.y + delta_y);
+
+        if let (Some((target, _, _, target_faction)), Some(faction)) = (target, factions.get(entity)) {
+            if faction_table.reaction(faction.kind, *target_faction) == factions::Reaction::Attack {
+                wants_melee.insert(entity, components::WantsToMelee{ target: *target }).expect("Add target failed");
+                continue;
+            }
+        }
 
-    for (_player, pos, viewshed) in (&mut players, &mut positions, &mut viewsheds).join() {
         //TODO: still stray off the map
-        pos.x = cmp::min(map.width - 1, cmp::max(0, pos.x + delta_x));
-        pos.y = cmp::min(map.height - 1, cmp::max(0, pos.y + delta_y));
+        let x = cmp::min(map.width - 1, cmp::max(0, pos.x + delta_x));
+        let y = cmp::min(map.height - 1, cmp::max(0, pos.y + delta_y));
+
+        // Walls, other creatures and deep fluids are in the way, closed doors open instead of being walked through
+        let faction = factions.get(entity).map(|f| f.kind);
+        let idx = map.xy_idx(x, y);
+        if map.blocked[idx] || !map.can_enter(idx) || !doors::try_pass(&mut *map, &mut doors, &mut noises, x, y, faction) || !fluids::wade(&map, &mut wading, entity, idx) {
+            continue;
+        }
+
+        pos.x = x;
+        pos.y = y;
 
         let mut player_position = ecs.write_resource::<components::PlayerPosition>();
         player_position.x = pos.x;
@@ -234,8 +602,222 @@ fn try_move_player(delta_x: i32, delta_y: i32, ecs: &World) {
     }
 }
 
-fn game_input(game: &mut Game, window: &mut Window) {
-    if window.keyboard()[Key::P] == ButtonState::Pressed {
+/// The selected unit picks up a loose item where it stands, or puts down everything it carries if there is nothing to pick up.
+fn pick_up_or_drop(ecs: &World) {
+    let entities = ecs.entities();
+    let selected = ecs.fetch::<components::SelectedUnit>();
+    let board = ecs.fetch::<jobs::JobBoard>();
+    let names = ecs.read_storage::<components::Name>();
+    let positions = ecs.read_storage::<components::Position>();
+    let items = ecs.read_storage::<components::Item>();
+    let mut carried = ecs.write_storage::<components::CarriedBy>();
+
+    let (unit, x, y) = match selected.entity.and_then(|entity| positions.get(entity).map(|pos| (entity, pos.x, pos.y))) {
+        Some(unit) => unit,
+        None => return
+    };
+    let unit_name = names.get(unit).map_or("Someone", |n| n.name.as_str());
+
+    // Items somebody is on their way to haul are left alone
+    let reserved = board.reserved_items();
+    let loose = (&entities, &items, &positions, !&carried).join()
+        .find(|(item, _, pos, _)| pos.x == x && pos.y == y && !reserved.contains(item))
+        .map(|(item, _, _, _)| item);
+
+    if let Some(item) = loose {
+        carried.insert(item, components::CarriedBy{ owner: unit }).expect("Unable to pick up item");
+        messages::post(format!("{} picks up the {}", unit_name, names.get(item).map_or("item", |n| n.name.as_str())));
+        return;
+    }
+
+    let held: Vec<Entity> = (&entities, &carried).join().filter(|(_, c)| c.owner == unit).map(|(item, _)| item).collect();
+    for item in held {
+        carried.remove(item);
+        messages::post(format!("{} puts down the {}", unit_name, names.get(item).map_or("item", |n| n.name.as_str())));
+    }
+}
+
+/// Keeps the selection pointing at a living unit and `PlayerPosition` pointing at the selection.
+fn update_selection(ecs: &World) {
+    let entities = ecs.entities();
+    let players = ecs.read_storage::<components::Player>();
+    let positions = ecs.read_storage::<components::Position>();
+    let mut selected = ecs.write_resource::<components::SelectedUnit>();
+    let mut player_position = ecs.write_resource::<components::PlayerPosition>();
+
+    let still_selectable = selected.entity.map_or(false, |entity| entities.is_alive(entity) && players.get(entity).is_some());
+    if !still_selectable {
+        selected.entity = (&entities, &players).join().map(|(entity, _player)| entity).next();
+    }
+
+    if let Some(pos) = selected.entity.and_then(|entity| positions.get(entity)) {
+        player_position.x = pos.x;
+        player_position.y = pos.y;
+    }
+}
+
+fn cycle_selection(ecs: &World) {
+    let entities = ecs.entities();
+    let players = ecs.read_storage::<components::Player>();
+    let mut selected = ecs.write_resource::<components::SelectedUnit>();
+
+    let units: Vec<Entity> = (&entities, &players).join().map(|(entity, _player)| entity).collect();
+    if units.is_empty() {
+        return;
+    }
+
+    let next = match selected.entity.and_then(|current| units.iter().position(|unit| *unit == current)) {
+        Some(index) => (index + 1) % units.len(),
+        None => 0
+    };
+
+    selected.entity = Some(units[next]);
+    ecs.write_resource::<components::Camera>().follow_selected = true;
+}
+
+fn order_move(ecs: &World) {
+    let (x, y, following) = {
+        let camera = ecs.fetch::<components::Camera>();
+        (camera.x, camera.y, camera.follow_selected)
+    };
+
+    if !following {
+        order_move_to(ecs, x, y);
+    }
+}
+
+/// Sends the selected unit walking to a tile.
+fn order_move_to(ecs: &World, x: i32, y: i32) {
+    let selected = ecs.fetch::<components::SelectedUnit>();
+    let mut move_orders = ecs.write_storage::<components::MoveOrder>();
+    let mut assigned_jobs = ecs.write_storage::<components::AssignedJob>();
+    let mut explorers = ecs.write_storage::<components::AutoExplore>();
+
+    if let Some(entity) = selected.entity {
+        assigned_jobs.remove(entity);
+        explorers.remove(entity);
+        move_orders.insert(entity, components::MoveOrder{ x, y }).expect("Unable to give move order");
+    }
+}
+
+/// Sets the selected unit exploring. Items it can already see won't stop it.
+fn start_exploring(ecs: &World) {
+    let entities = ecs.entities();
+    let selected = ecs.fetch::<components::SelectedUnit>();
+    let positions = ecs.read_storage::<components::Position>();
+    let viewsheds = ecs.read_storage::<components::Viewshed>();
+    let items = ecs.read_storage::<components::Item>();
+    let mut move_orders = ecs.write_storage::<components::MoveOrder>();
+    let mut assigned_jobs = ecs.write_storage::<components::AssignedJob>();
+    let mut explorers = ecs.write_storage::<components::AutoExplore>();
+
+    let entity = match selected.entity {
+        Some(entity) => entity,
+        None => return
+    };
+
+    let seen_items = match viewsheds.get(entity) {
+        Some(viewshed) => (&entities, &positions, &items).join()
+            .filter(|(_, pos, _)| viewshed.visible_tiles.iter().any(|tile| tile.x == pos.x && tile.y == pos.y))
+            .map(|(item, _, _)| item)
+            .collect(),
+        None => Vec::new()
+    };
+
+    move_orders.remove(entity);
+    assigned_jobs.remove(entity);
+    explorers.insert(entity, components::AutoExplore{ seen_items }).expect("Unable to start exploring");
+    ecs.write_resource::<components::Camera>().follow_selected = true;
+}
+
+/// The fort has no stairs, the way out is up to the surface. Heads for the nearest open ground that has been seen.
+fn travel_up(ecs: &World) {
+    let (from_x, from_y) = {
+        let player_pos = ecs.fetch::<components::PlayerPosition>();
+        (player_pos.x, player_pos.y)
+    };
+
+    let destination = {
+        let map = ecs.fetch::<map::Map>();
+        (0..map.surface.len())
+            .filter(|idx| map.surface[*idx] && map.revealed_map[*idx] && map.glyph_map[*idx] != '#' && !map.fluid_blocks(*idx))
+            .map(|idx| (idx as i32 % map.width, idx as i32 / map.width))
+            .min_by_key(|(x, y)| (x - from_x).pow(2) + (y - from_y).pow(2))
+    };
+
+    match destination {
+        Some((x, y)) => order_move_to(ecs, x, y),
+        None => messages::post("No way up has been found yet".to_string())
+    }
+}
+
+/// The first press marks one corner of a new stockpile, the second press the opposite corner.
+fn designate_stockpile(ecs: &World) {
+    let (x, y) = camera::cursor_tile(ecs);
+    let mut stockpiles = ecs.write_resource::<stockpiles::Stockpiles>();
+
+    match stockpiles.pending_corner.take() {
+        None => {
+            messages::post(format!("Stockpile corner set at {}, {}", x, y));
+            stockpiles.pending_corner = Some((x, y));
+        }
+        Some(corner) => {
+            let zone = stockpiles::StockpileZone::new(corner, (x, y));
+            messages::post(format!("Stockpile created accepting {}", zone.filter_name()));
+            stockpiles.zones.push(zone);
+        }
+    }
+}
+
+fn cycle_stockpile_filter(ecs: &World) {
+    let mut stockpiles = ecs.write_resource::<stockpiles::Stockpiles>();
+
+    if let Some(zone) = stockpiles.zones.last_mut() {
+        zone.cycle_filter();
+        messages::post(format!("Stockpile now accepts {}", zone.filter_name()));
+    }
+}
+
+/// Queues the workshop under the cursor's selected recipe, or with `cycle` set, moves on to its next recipe.
+fn workshop_orders(ecs: &World, cycle: bool) {
+    let (x, y) = camera::cursor_tile(ecs);
+    let workshop_entity = match workshops::workshop_at(ecs, x, y) {
+        Some(entity) => entity,
+        None => return
+    };
+
+    let raws = ecs.fetch::<raws::Raws>();
+    let mut workshops = ecs.write_storage::<components::Workshop>();
+    let workshop = match workshops.get_mut(workshop_entity) {
+        Some(workshop) => workshop,
+        None => return
+    };
+
+    let recipes = raws.recipes_for(workshop.kind);
+    if recipes.is_empty() {
+        return;
+    }
+
+    if cycle {
+        workshop.selected_recipe = (workshop.selected_recipe + 1) % recipes.len();
+        messages::post(format!("{}: {}", workshop.kind.name(), raws.recipes[recipes[workshop.selected_recipe]].name));
+    } else {
+        let recipe = recipes[workshop.selected_recipe % recipes.len()];
+        workshop.queue.push(recipe);
+        messages::post(format!("{} queued at the {} ({} in queue)", raws.recipes[recipe].name, workshop.kind.name(), workshop.queue.len()));
+    }
+}
+
+/// Returns false once the player has asked to leave the game.
+fn game_input(game: &mut Game) -> bool {
+    use input::Action;
+
+    let (pause, quit, look, fire) = {
+        let mut queue = game.ecs.write_resource::<input::InputQueue>();
+        (queue.take(Action::Pause), queue.take(Action::Quit), queue.take(Action::Look), queue.take(Action::Fire))
+    };
+
+    if pause {
         if game.runstate == RunState::Running {
             game.runstate = RunState::Paused; 
         } else {
@@ -243,28 +825,271 @@ fn game_input(game: &mut Game, window: &mut Window) {
         }    
     }
 
-    if window.keyboard()[Key::Escape].is_down() {
-        window.close();
+    if fire && game.look.is_none() {
+        if game.targeting.is_some() {
+            fire_at_target(game);
+        } else {
+            start_targeting(game);
+        }
     }
+
+    // Escape backs out of aiming or looking rather than leaving the game
+    if quit && game.targeting.is_some() {
+        game.targeting = None;
+    } else if game.targeting.is_none() && (look || (quit && game.look.is_some())) {
+        look::toggle_look(game);
+    } else if quit {
+        return false;
+    }
+
+    true
 }
 
-fn player_input(game: &mut Game, window: &mut Window) {
-    use ButtonState::*;
+/// Hostiles the unit can see, nearest first.
+fn visible_hostiles(ecs: &World, unit: Entity) -> Vec<Entity> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<components::Position>();
+    let viewsheds = ecs.read_storage::<components::Viewshed>();
+    let factions = ecs.read_storage::<components::Faction>();
+    let combat_stats = ecs.read_storage::<components::CombatStats>();
+    let faction_table = ecs.fetch::<factions::FactionTable>();
+
+    let (origin, viewshed, faction) = match (positions.get(unit), viewsheds.get(unit), factions.get(unit)) {
+        (Some(pos), Some(viewshed), Some(faction)) => (pos, viewshed, faction),
+        _ => return Vec::new()
+    };
+
+    let mut hostiles: Vec<(Entity, i32)> = (&entities, &positions, &factions, &combat_stats).join()
+        .filter(|(entity, _, other, stats)| *entity != unit && stats.hp > 0 && faction_table.reaction(faction.kind, other.kind) == factions::Reaction::Attack)
+        .filter(|(_, pos, _, _)| viewshed.visible_tiles.iter().any(|tile| tile.x == pos.x && tile.y == pos.y))
+        .map(|(entity, pos, _, _)| (entity, i32::max((pos.x - origin.x).abs(), (pos.y - origin.y).abs())))
+        .collect();
 
-    if window.keyboard()[Key::Left] == Pressed {
-        try_move_player(-1, 0, &game.ecs);
+    hostiles.sort_by_key(|(_, distance)| *distance);
+    hostiles.into_iter().map(|(entity, _)| entity).collect()
+}
+
+fn start_targeting(game: &mut Game) {
+    let shooter = match game.ecs.fetch::<components::SelectedUnit>().entity {
+        Some(shooter) => shooter,
+        None => return
+    };
+
+    {
+        let entities = game.ecs.entities();
+        let carried = game.ecs.read_storage::<components::CarriedBy>();
+        let ranged = game.ecs.read_storage::<components::Ranged>();
+
+        if ranged::ranged_weapon(shooter, &entities, &carried, &ranged).is_none() {
+            messages::post("Nothing to shoot or throw".to_string());
+            return;
+        }
     }
-    if window.keyboard()[Key::Right] == Pressed {
-        try_move_player(1, 0, &game.ecs);
+
+    let targets = visible_hostiles(&game.ecs, shooter);
+    if targets.is_empty() {
+        messages::post("No hostiles in sight".to_string());
+        return;
     }
-    if window.keyboard()[Key::Up] == Pressed {
-        try_move_player(0, -1, &game.ecs);
+
+    game.targeting = Some(Targeting { shooter, targets, index: 0 });
+}
+
+fn fire_at_target(game: &mut Game) {
+    let targeting = match game.targeting.take() {
+        Some(targeting) => targeting,
+        None => return
+    };
+
+    let positions = game.ecs.read_storage::<components::Position>();
+    let mut wants_shoot = game.ecs.write_storage::<components::WantsToShoot>();
+
+    if let Some(pos) = positions.get(targeting.targets[targeting.index]) {
+        wants_shoot.insert(targeting.shooter, components::WantsToShoot{ x: pos.x, y: pos.y }).expect("Unable to aim");
+    }
+}
+
+/// Tab and the movement keys cycle through the targets, east and south to the next one, west and north back.
+fn targeting_input(game: &mut Game) {
+    use input::Action;
+
+    let actions = game.ecs.write_resource::<input::InputQueue>().drain();
+    let targeting = match game.targeting.as_mut() {
+        Some(targeting) => targeting,
+        None => return
+    };
+
+    let count = targeting.targets.len();
+    for action in actions {
+        let forward = match (action, action.direction()) {
+            (Action::SelectNext, _) => true,
+            (_, Some((delta_x, delta_y))) => delta_x + delta_y > 0,
+            _ => continue
+        };
+
+        targeting.index = if forward { (targeting.index + 1) % count } else { (targeting.index + count - 1) % count };
     }
-    if window.keyboard()[Key::Down] == Pressed {
-        try_move_player(0, 1, &game.ecs);
+}
+
+/// The line of fire from the shooter to the current target, cut short where it runs out of range.
+fn targeting_preview(ecs: &World, targeting: &Targeting) -> Vec<((i32, i32), ranged::Segment)> {
+    let map = ecs.fetch::<map::Map>();
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<components::Position>();
+    let combat_stats = ecs.read_storage::<components::CombatStats>();
+    let carried = ecs.read_storage::<components::CarriedBy>();
+    let ranged = ecs.read_storage::<components::Ranged>();
+
+    let (from, to) = match (positions.get(targeting.shooter), positions.get(targeting.targets[targeting.index])) {
+        (Some(from), Some(to)) => ((from.x, from.y), (to.x, to.y)),
+        _ => return Vec::new()
+    };
+    let range = ranged::ranged_weapon(targeting.shooter, &entities, &carried, &ranged)
+        .and_then(|weapon| ranged.get(weapon))
+        .map_or(0, |r| r.range);
+
+    let creatures: Vec<(i32, i32)> = (&entities, &positions, &combat_stats).join()
+        .filter(|(entity, _, stats)| *entity != targeting.shooter && stats.hp > 0)
+        .map(|(_, pos, _)| (pos.x, pos.y))
+        .collect();
+
+    let path: Vec<(i32, i32)> = map::line(from, to).into_iter().skip(1).collect();
+    let segments = ranged::line_of_fire(&map, &path, &creatures);
+
+    path.into_iter().zip(segments.into_iter()).enumerate()
+        .map(|(distance, (tile, segment))| (tile, if distance as i32 >= range { ranged::Segment::Blocked } else { segment }))
+        .collect()
+}
+
+fn designate_job(kind: jobs::JobKind, delta_x: i32, delta_y: i32, ecs: &World) {
+    let player_pos = ecs.fetch::<components::PlayerPosition>();
+    let map = ecs.fetch::<map::Map>();
+    let mut board = ecs.write_resource::<jobs::JobBoard>();
+
+    let (x, y) = (player_pos.x + delta_x, player_pos.y + delta_y);
+    if !map.in_bounds(x, y) {
+        return;
     }
-    if window.keyboard()[Key::Escape].is_down() {
-        window.close();
+
+    if kind == jobs::JobKind::Dig && map.glyph_map[map.xy_idx(x, y)] != '#' {
+        return;
+    }
+
+    board.designate(kind, x, y);
+}
+
+fn player_input(game: &mut Game) {
+    use input::Action;
+
+    let (actions, digging, panning) = {
+        let mut queue = game.ecs.write_resource::<input::InputQueue>();
+        (queue.drain(), queue.is_held(Action::Dig), queue.is_held(Action::Pan))
+    };
+
+    for action in actions {
+        // Holding dig while moving designates the neighbouring tile for digging instead of moving,
+        // holding pan scrolls the camera instead
+        if let Some((delta_x, delta_y)) = action.direction() {
+            if panning {
+                camera::pan_camera(delta_x, delta_y, &game.ecs);
+            } else if digging {
+                designate_job(jobs::JobKind::Dig, delta_x, delta_y, &game.ecs);
+            } else {
+                try_move_player(delta_x, delta_y, &game.ecs);
+            }
+            continue;
+        }
+
+        match action {
+            Action::Wait => {}
+            Action::PickUp => pick_up_or_drop(&game.ecs),
+            Action::Inventory => {
+                game.show_inventory = !game.show_inventory;
+                game.show_unit_info = false;
+            }
+            Action::UnitInfo => {
+                game.show_unit_info = !game.show_unit_info;
+                game.show_inventory = false;
+            }
+            Action::SelectNext => cycle_selection(&game.ecs),
+            Action::MoveOrder => order_move(&game.ecs),
+            Action::Stockpile => designate_stockpile(&game.ecs),
+            Action::StockpileFilter => cycle_stockpile_filter(&game.ecs),
+            Action::NextWorkshop => {
+                game.workshop_kind = (game.workshop_kind + 1) % workshops::WORKSHOP_KINDS.len();
+                messages::post(format!("Next workshop: {}", workshops::WORKSHOP_KINDS[game.workshop_kind].name()));
+            }
+            Action::PlaceWorkshop => {
+                let (x, y) = camera::cursor_tile(&game.ecs);
+                let kind = workshops::WORKSHOP_KINDS[game.workshop_kind];
+
+                if !workshops::place_workshop(&mut game.ecs, kind, x, y) {
+                    messages::post(format!("There is no room for a {} here", kind.name()));
+                }
+            }
+            Action::QueueRecipe => workshop_orders(&game.ecs, false),
+            Action::NextRecipe => workshop_orders(&game.ecs, true),
+            Action::BuildMode => {
+                game.build_mode = match game.build_mode {
+                    None => Some(0),
+                    Some(kind) if kind + 1 < construction::CONSTRUCTION_KINDS.len() => Some(kind + 1),
+                    Some(_) => None
+                };
+
+                match game.build_mode {
+                    Some(kind) => messages::post(format!("Build mode: {}", construction::CONSTRUCTION_KINDS[kind].name())),
+                    None => messages::post("Left build mode".to_string())
+                }
+            }
+            Action::PlaceBlueprint => {
+                if let Some(kind) = game.build_mode {
+                    let (x, y) = camera::cursor_tile(&game.ecs);
+                    let kind = construction::CONSTRUCTION_KINDS[kind];
+
+                    if !construction::place_blueprint(&mut game.ecs, kind, x, y) {
+                        messages::post(format!("A {} can't be built here", kind.name().to_lowercase()));
+                    }
+                }
+            }
+            Action::CycleLock => {
+                let (x, y) = camera::cursor_tile(&game.ecs);
+                doors::cycle_lock(&game.ecs, x, y);
+            }
+            Action::Deconstruct => {
+                let (x, y) = camera::cursor_tile(&game.ecs);
+
+                if !construction::designate_deconstruction(&game.ecs, x, y) {
+                    messages::post("Nothing has been built here".to_string());
+                }
+            }
+            Action::ZoomIn => camera::zoom_camera(&game.ecs, true),
+            Action::ZoomOut => camera::zoom_camera(&game.ecs, false),
+            Action::SnapCamera => camera::snap_camera(&game.ecs),
+            Action::NextTileset => {
+                game.tileset_index = (game.tileset_index + 1) % game.tilesets.len();
+                let _ = game.tilesets[game.tileset_index].execute(|tileset| {
+                    messages::post(format!("Tileset: {}", tileset.name));
+                    Ok(())
+                });
+            }
+            Action::Explore => start_exploring(&game.ecs),
+            Action::TravelUp => travel_up(&game.ecs),
+            Action::MarkLocation => {
+                let (x, y) = camera::cursor_tile(&game.ecs);
+                game.marked = Some((x, y));
+                messages::post(format!("Marked {}, {}", x, y));
+            }
+            Action::TravelToMark => match game.marked {
+                Some((x, y)) => order_move_to(&game.ecs, x, y),
+                None => messages::post("Nothing has been marked".to_string())
+            },
+            Action::Shout => {
+                let player_pos = game.ecs.fetch::<components::PlayerPosition>();
+                messages::post("You shout".to_string());
+                game.ecs.write_resource::<noise::NoiseQueue>().emit(player_pos.x, player_pos.y, noise::SHOUT_LOUDNESS);
+            }
+            _ => {}
+        }
     }
 }
 
@@ -278,13 +1103,91 @@ fn register_components(ecs: &mut World) {
     ecs.register::<components::Viewshed>();
     ecs.register::<components::Name>();
     ecs.register::<components::BlocksTile>();
+    ecs.register::<components::Faction>();
+    ecs.register::<components::CombatStats>();
+    ecs.register::<components::WantsToMelee>();
+    ecs.register::<components::SufferDamage>();
+    ecs.register::<components::Hearing>();
+    ecs.register::<components::Investigating>();
+    ecs.register::<components::Dwarf>();
+    ecs.register::<components::AssignedJob>();
+    ecs.register::<components::MoveOrder>();
+    ecs.register::<components::Hunger>();
+    ecs.register::<components::Thirst>();
+    ecs.register::<components::Fatigue>();
+    ecs.register::<components::Seeking>();
+    ecs.register::<components::Asleep>();
+    ecs.register::<components::Wading>();
+    ecs.register::<components::Edible>();
+    ecs.register::<components::Drinkable>();
+    ecs.register::<components::Bed>();
+    ecs.register::<components::Item>();
+    ecs.register::<components::CarriedBy>();
+    ecs.register::<components::Workshop>();
+    ecs.register::<components::Blueprint>();
+    ecs.register::<components::Constructed>();
+    ecs.register::<components::Door>();
+    ecs.register::<components::Temperature>();
+    ecs.register::<components::Flammable>();
+    ecs.register::<components::Burning>();
+    ecs.register::<components::Crop>();
+    ecs.register::<components::Skills>();
+    ecs.register::<components::Quality>();
+    ecs.register::<components::MadeOf>();
+    ecs.register::<components::Weight>();
+    ecs.register::<components::Value>();
+    ecs.register::<components::Weapon>();
+    ecs.register::<components::Armour>();
+    ecs.register::<components::Ranged>();
+    ecs.register::<components::WantsToShoot>();
+    ecs.register::<components::AutoExplore>();
 }
 
 fn run_systems(ecs: &mut World) {
+    ecs.write_resource::<scheduler::Scheduler>().advance();
+
+    let mut calendar = calendar::CalendarSystem{};
+    calendar.run_now(ecs);
     let mut rw = components::RandomMover{};
     rw.run_now(ecs);
     let mut mob = systems::MonsterAI{};
     mob.run_now(ecs);
+    let mut needs = needs::NeedsSystem{};
+    needs.run_now(ecs);
+    let mut seeking = needs::SeekingSystem{};
+    seeking.run_now(ecs);
+    let mut melee = systems::MeleeCombatSystem{};
+    melee.run_now(ecs);
+    let mut ranged = ranged::RangedCombatSystem{};
+    ranged.run_now(ecs);
+    let mut damage = systems::DamageSystem{};
+    damage.run_now(ecs);
+    let mut hearing = noise::HearingSystem{};
+    hearing.run_now(ecs);
+    let mut move_orders = systems::MoveOrderSystem{};
+    move_orders.run_now(ecs);
+    let mut explore = systems::AutoExploreSystem{};
+    explore.run_now(ecs);
+    let mut job_release = jobs::JobReleaseSystem{};
+    job_release.run_now(ecs);
+    let mut workshop_orders = workshops::WorkshopSystem{};
+    workshop_orders.run_now(ecs);
+    let mut construction = construction::ConstructionSystem{};
+    construction.run_now(ecs);
+    let mut hauling = stockpiles::HaulingSystem{};
+    hauling.run_now(ecs);
+    let mut job_assignment = jobs::JobAssignmentSystem{};
+    job_assignment.run_now(ecs);
+    let mut job_work = jobs::JobWorkSystem{};
+    job_work.run_now(ecs);
+    let mut fluids = fluids::FluidSystem{};
+    fluids.run_now(ecs);
+    let mut heat = heat::HeatSystem{};
+    heat.run_now(ecs);
+    let mut doors = doors::DoorSystem{};
+    doors.run_now(ecs);
+    let mut carry = systems::CarrySystem{};
+    carry.run_now(ecs);
     let mut vis = systems::VisibilitySystem{};
     vis.run_now(ecs);
     let mut gm = systems::GlyphMapper{};
@@ -292,40 +1195,334 @@ fn run_systems(ecs: &mut World) {
     let mut mapindex = systems::MapIndexingSystem{};
     mapindex.run_now(ecs);
 
+    systems::delete_the_dead(ecs);
     ecs.maintain();
+    update_selection(ecs);
+}
+
+/// Draws the map view and the panels around it. Both the window and the terminal draw the screen with this.
+fn render_screen(game: &Game, renderer: &mut dyn Renderer) -> Result<()> {
+    renderer.fill(Rectangle::new(game.screen_layout.bottom_panel_origin_pixels, game.screen_layout.bottom_panel_size_pixels), Color::BLACK)?;
+    renderer.fill(Rectangle::new(game.screen_layout.right_panel_origin_pixels, game.screen_layout.right_panel_size_pixels), Color::BLACK)?;
+    // Zoomed out, the tiles don't always fill the map view to the edges
+    renderer.fill(Rectangle::new(game.screen_layout.screen_origin.times(game.screen_layout.tile_size_pixels),
+                                 game.screen_layout.screen_size.times(game.screen_layout.tile_size_pixels)), Color::BLACK)?;
+
+    let line_end = Vector::new(game.screen_layout.right_panel_origin_pixels.x, game.screen_layout.right_panel_origin_pixels.y + game.screen_layout.window_size.times(game.screen_layout.tile_size_pixels).y);
+    renderer.line(game.screen_layout.right_panel_origin_pixels, line_end, 2.0, Color::WHITE)?;
+
+    let line_end_2 = Vector::new(game.screen_layout.right_panel_origin_pixels.x, game.screen_layout.bottom_panel_origin_pixels.y); 
+    renderer.line(game.screen_layout.bottom_panel_origin_pixels, line_end_2, 2.0, Color::WHITE)?;
+
+    // What is being built comes first, and the latest messages fill the rest of the bottom panel
+    let mut message_area = Rectangle::new(game.screen_layout.bottom_panel_origin_pixels, game.screen_layout.bottom_panel_size_pixels);
+    if let Some(kind) = game.build_mode {
+        let text = format!("Building: {}", construction::CONSTRUCTION_KINDS[kind].name());
+        renderer.text(&text, message_area.pos + Vector::new(4.0, 0.0), 14.0, Color::YELLOW)?;
+        let row = Vector::new(0.0, messages::MESSAGE_ROW_PIXELS);
+        message_area = Rectangle::new(message_area.pos + row, message_area.size - row);
+    }
+    messages::render_messages(renderer, message_area)?;
+
+    if game.runstate == RunState::Paused {
+        renderer.text("Paused", game.screen_layout.right_panel_origin_pixels, 20.0, Color::WHITE)?;
+    }
+
+    match (&game.look, &game.targeting) {
+        (Some(look), _) => look::render_look_panel(renderer, &game.ecs, &game.screen_layout, look.x, look.y)?,
+        (_, Some(targeting)) => render_targeting_panel(renderer, &game.ecs, &game.screen_layout, targeting)?,
+        _ => render_unit_panel(renderer, &game.ecs, &game.screen_layout)?
+    }
+
+    minimap::render_minimap(renderer, &game.ecs, &game.screen_layout, game.marked)?;
+
+    let date = game.ecs.fetch::<calendar::Calendar>().describe();
+    let date_position = game.screen_layout.right_panel_origin_pixels + Vector::new(10.0, game.screen_layout.right_panel_size_pixels.y - 40.0);
+    renderer.text(&date, date_position, 14.0, Color::WHITE)?;
+
+    let positions = game.ecs.read_storage::<components::Position>();
+    let renderables = game.ecs.read_storage::<components::Renderable>();
+
+    let map = game.ecs.fetch::<map::Map>();
+    let focus = camera::camera_focus(&game.ecs);
+    let camera = game.ecs.fetch::<components::Camera>();
+    let selected = game.ecs.fetch::<components::SelectedUnit>();
+
+    let names = game.ecs.read_storage::<components::Name>();
+    let zoom = camera::ZOOM_LEVELS[camera.zoom_level];
+    let view_size = game.screen_layout.view_size(zoom);
+    let offset_px = game.screen_layout.screen_origin.times(game.screen_layout.tile_size_pixels);
+    let tile_pixels = game.screen_layout.tile_size_pixels * zoom;
+
+    let entities = game.ecs.entities();
+    let tiles = game.ecs.read_storage::<components::Tile>();
+
+    // Fluids replace the floor glyph underneath them, shaded by depth
+    for (idx, cell) in map.fluids.iter().enumerate() {
+        if cell.depth == 0 || !map.revealed_map[idx] {
+            continue;
+        }
+
+        let position = Vector::new(idx as i32 % map.width, idx as i32 / map.width);
+        let mapped_position = camera::camera_translate(focus, position, Vector::new(map.width, map.height), view_size);
+
+        if !camera::should_render(mapped_position, view_size) {
+            continue;
+        }
+
+        let colour = if map.visible_map[idx] { fluids::fluid_colour(*cell) } else { Color::BLACK.with_alpha(0.5) };
+        renderer.tile(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), '~', None, colour)?;
+    }
+
+    for (entity, pos, render) in (&entities, &positions, &renderables).join() {
+        let position = Vector::new(pos.x, pos.y);
+        let idx = map.point2d_to_index(rltk::Point::new(pos.x, pos.y));
+
+        let visible = map.visible_map[idx];
+        let revealed = map.revealed_map[idx];
+
+        if revealed && map.fluids[idx].depth > 0 && tiles.get(entity).is_some() {
+            continue;
+        }
+
+        let mapped_position = camera::camera_translate(focus, position, Vector::new(map.width, map.height), view_size);
+        let area = Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels);
+
+        if !camera::should_render(mapped_position, view_size) {
+            continue;
+        }
+
+        let name = names.get(entity).map(|n| n.name.as_str());
+
+        if visible {
+            renderer.tile(area, render.glyph, name, render.color)?;
+        } else if revealed && !visible {
+            renderer.tile(area, render.glyph, name, Color::BLACK.with_alpha(0.5))?;
+        } else {
+            renderer.fill(area, Color::BLACK)?;
+        }
+    }
+
+    let workshops = game.ecs.read_storage::<components::Workshop>();
+
+    for (workshop, pos) in (&workshops, &positions).join() {
+        let alpha = if workshop.built { 0.3 } else { 0.1 };
+
+        for (x, y) in workshops::footprint(pos.x, pos.y) {
+            let mapped_position = camera::camera_translate(focus, Vector::new(x, y), Vector::new(map.width, map.height), view_size);
+
+            if !camera::should_render(mapped_position, view_size) {
+                continue;
+            }
+
+            renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), Color::from_rgba(110, 70, 30, alpha))?;
+        }
+    }
+
+    let stockpiles = game.ecs.fetch::<stockpiles::Stockpiles>();
+
+    for zone in stockpiles.zones.iter() {
+        for (x, y) in zone.floor_tiles(&map) {
+            let mapped_position = camera::camera_translate(focus, Vector::new(x, y), Vector::new(map.width, map.height), view_size);
+
+            if !camera::should_render(mapped_position, view_size) {
+                continue;
+            }
+
+            renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), Color::GREEN.with_alpha(0.2))?;
+        }
+    }
+
+    let blueprints = game.ecs.read_storage::<components::Blueprint>();
+
+    for (_blueprint, pos) in (&blueprints, &positions).join() {
+        let mapped_position = camera::camera_translate(focus, Vector::new(pos.x, pos.y), Vector::new(map.width, map.height), view_size);
+
+        if !camera::should_render(mapped_position, view_size) {
+            continue;
+        }
+
+        renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), Color::BLUE.with_alpha(0.2))?;
+    }
+
+    // The surface darkens as night falls
+    let daylight = game.ecs.fetch::<calendar::Calendar>().daylight();
+
+    if daylight < 1.0 {
+        for (idx, _surface) in map.surface.iter().enumerate().filter(|(idx, surface)| **surface && map.revealed_map[*idx]) {
+            let position = Vector::new(idx as i32 % map.width, idx as i32 / map.width);
+            let mapped_position = camera::camera_translate(focus, position, Vector::new(map.width, map.height), view_size);
+
+            if !camera::should_render(mapped_position, view_size) {
+                continue;
+            }
+
+            renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), Color::from_rgba(10, 10, 60, (1.0 - daylight) * 0.7))?;
+        }
+    }
+
+    // Fires and the smoke they give off, over whatever can currently be seen
+    for (idx, (fire, smoke)) in map.fire.iter().zip(map.smoke.iter()).enumerate() {
+        if (*fire == 0 && *smoke == 0) || !map.visible_map[idx] {
+            continue;
+        }
+
+        let position = Vector::new(idx as i32 % map.width, idx as i32 / map.width);
+        let mapped_position = camera::camera_translate(focus, position, Vector::new(map.width, map.height), view_size);
+
+        if !camera::should_render(mapped_position, view_size) {
+            continue;
+        }
+
+        let colour = if *fire > 0 {
+            Color::ORANGE.with_alpha(0.5)
+        } else {
+            Color::from_rgba(90, 90, 90, *smoke as f32 / heat::MAX_SMOKE as f32 * 0.6)
+        };
+
+        renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), colour)?;
+    }
+
+    let board = game.ecs.fetch::<jobs::JobBoard>();
+
+    for job in board.jobs.iter() {
+        let mapped_position = camera::camera_translate(focus, Vector::new(job.x, job.y), Vector::new(map.width, map.height), view_size);
+
+        if !camera::should_render(mapped_position, view_size) {
+            continue;
+        }
+
+        renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), Color::YELLOW.with_alpha(0.3))?;
+    }
+
+    if let Some(pos) = selected.entity.and_then(|entity| positions.get(entity)) {
+        let mapped_position = camera::camera_translate(focus, Vector::new(pos.x, pos.y), Vector::new(map.width, map.height), view_size);
+
+        if camera::should_render(mapped_position, view_size) {
+            renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), Color::CYAN.with_alpha(0.3))?;
+        }
+    }
+
+    if let Some((x, y)) = game.marked {
+        let mapped_position = camera::camera_translate(focus, Vector::new(x, y), Vector::new(map.width, map.height), view_size);
+
+        if camera::should_render(mapped_position, view_size) {
+            renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), Color::PURPLE.with_alpha(0.3))?;
+        }
+    }
+
+    // Green where the shot flies clear, yellow where someone might get in the way, red past a wall or out of range
+    if let Some(targeting) = &game.targeting {
+        for (tile, segment) in targeting_preview(&game.ecs, targeting) {
+            let mapped_position = camera::camera_translate(focus, Vector::new(tile.0, tile.1), Vector::new(map.width, map.height), view_size);
+
+            if !camera::should_render(mapped_position, view_size) {
+                continue;
+            }
+
+            let colour = match segment {
+                ranged::Segment::Clear => Color::GREEN,
+                ranged::Segment::Occupied => Color::YELLOW,
+                ranged::Segment::Blocked => Color::RED
+            };
+
+            renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), colour.with_alpha(0.4))?;
+        }
+    }
+
+    if let Some(look) = &game.look {
+        let mapped_position = camera::camera_translate(focus, Vector::new(look.x, look.y), Vector::new(map.width, map.height), view_size);
+
+        renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), Color::WHITE.with_alpha(0.4))?;
+    } else if !camera.follow_selected {
+        // While free-scrolling, the centre of the view is the cursor for move orders
+        let mapped_position = camera::camera_translate(focus, focus, Vector::new(map.width, map.height), view_size);
+
+        renderer.fill(Rectangle::new(offset_px + mapped_position.times(tile_pixels), tile_pixels), Color::MAGENTA.with_alpha(0.3))?;
+    }
+
+    if game.show_unit_info {
+        render_unit_info(renderer, &game.ecs, &game.screen_layout)?;
+    }
+    if game.show_inventory {
+        render_inventory(renderer, &game.ecs, &game.screen_layout)?;
+    }
+
+    if game.context_menu.is_some() {
+        mouse::render_context_menu(renderer, game)?;
+    } else {
+        mouse::render_tooltip(renderer, &game.ecs, &game.screen_layout)?;
+    }
+
+    /*
+    let full_health_width_px = 100.0;
+    let current_health_width_px = (50 as f32 / 100 as f32) * full_health_width_px;
+
+    let health_bar_pos_px = Vector::new(screen_width_tiles * TILE_EDGE_PIXELS, 0.0);
+    let mana_bar_pos_px = Vector::new(screen_width_tiles * TILE_EDGE_PIXELS, -TILE_EDGE_PIXELS);
+
+    render_bar(renderer, Color::RED, current_health_width_px, health_bar_pos_px, full_health_width_px, tile_size_px)?;
+    render_bar(renderer, Color::BLUE, current_health_width_px, mana_bar_pos_px, full_health_width_px, tile_size_px)?;
+    */
+
+    Ok(())
+}
+
+impl Game {
+    /// Acts on this frame's input and moves the game on. Returns false once the player has asked to leave.
+    fn step(&mut self) -> bool {
+        let playing = game_input(self);
+
+        if self.look.is_some() {
+            look::look_input(self);
+        } else if self.targeting.is_some() {
+            targeting_input(self);
+        } else {
+            camera::edge_scroll(self);
+
+            if self.runstate == RunState::Running {
+                run_systems(&mut self.ecs);
+                player_input(self);
+                mouse::mouse_input(self);
+            }
+        }
+
+        // Whatever wasn't acted on this frame, e.g. while paused, is dropped rather than piling up
+        self.ecs.write_resource::<input::InputQueue>().clear();
+
+        playing
+    }
 }
 
 impl State for Game {
     /// Load the assets and initialise the game
     fn new() -> Result<Self> {
-        let mut screen_layout = ScreenLayout::new(Vector::new(TILE_EDGE_PIXELS, TILE_EDGE_PIXELS), 
-                                                  Vector::new(WINDOW_WIDTH_TILES, WINDOW_HEIGHT_TILES), 
-                                                  Vector::new(SCREEN_WIDTH_TILES, SCREEN_HEIGHT_TILES), 
-                                                  Vector::new(SCREEN_ORIGIN_X_TILES, SCREEN_ORIGIN_Y_TILES));
-
-        screen_layout.set_pixel_sizes();
+        let panels = PanelSizes::load();
+        let window_pixels = Vector::new(WINDOW_WIDTH_TILES * TILE_EDGE_PIXELS, WINDOW_HEIGHT_TILES * TILE_EDGE_PIXELS);
+        let screen_layout = ScreenLayout::fit(Vector::new(TILE_EDGE_PIXELS, TILE_EDGE_PIXELS), window_pixels, &panels);
 
         let font_square = "Square.ttf";
-        let game_glyphs = "#@g.%|_o*";
         let tile_size_px = screen_layout.tile_size_pixels; 
-        
-        let tileset = Asset::new(Font::load(font_square).and_then(move |text| {
-            let tiles = text
-                .render(game_glyphs, &FontStyle::new(tile_size_px.y, Color::WHITE))
-                .expect("Could not render the font tileset.");
-            let mut tileset = HashMap::new();
-            for (index, glyph) in game_glyphs.chars().enumerate() {
-                let pos = (index as i32 * tile_size_px.x as i32, 0);
-                let tile = tiles.subimage(Rectangle::new(pos, tile_size_px));
-                tileset.insert(glyph, tile);
-            }
-            Ok(tileset)
-        }));
+
+        let tilesets = tileset::definitions().iter()
+            .map(|definition| tileset::load(definition, font_square, tile_size_px))
+            .collect();
 
         let mut ecs = World::new();
         register_components(&mut ecs);
+        ecs.insert(scheduler::Scheduler::new(scheduler::FRAMES_PER_TURN));
+        ecs.insert(factions::FactionTable::new());
+        ecs.insert(noise::NoiseQueue::default());
+        ecs.insert(jobs::JobBoard::default());
+        ecs.insert(stockpiles::Stockpiles::default());
+        ecs.insert(raws::Raws::load());
+        ecs.insert(fluids::FluidSettings::load());
+        ecs.insert(calendar::Calendar::new());
+        ecs.insert(input::Keymap::load());
+        ecs.insert(input::InputQueue::default());
 
         generate_entities(&mut ecs);
+        update_selection(&ecs);
         let map_size = Vector::new(60, 50);
         map::generate_map_new(&mut ecs, map_size);
 
@@ -334,128 +1531,130 @@ impl State for Game {
             for _loop in 1..7 { map::apply_ca(&ecs, &mut new_map); }
         }
 
+        calendar::carve_surface(&mut ecs, (40, 25));
+        geology::lay_down_rock(&mut ecs);
+        generate_supplies(&mut ecs);
+        calendar::plant_crops(&mut ecs, (40, 25));
+        fluids::place_fluids(&mut ecs);
+
 
         Ok(Self {
-            tileset,
+            tilesets,
+            tileset_index: 0,
             screen_layout,
+            panels,
+            window_pixels,
             ecs,
-            runstate : RunState::Running
+            runstate : RunState::Running,
+            workshop_kind: 0,
+            build_mode: None,
+            show_unit_info: false,
+            show_inventory: false,
+            context_menu: None,
+            look: None,
+            targeting: None,
+            marked: None,
+            edge_scroll_timer: 0
         })
     }
 
     /// Process keyboard and mouse, update the game state
     fn update(&mut self, window: &mut Window) -> Result<()> {
-        game_input(self, window);
+        {
+            let keymap = self.ecs.fetch::<input::Keymap>();
+            let mut queue = self.ecs.write_resource::<input::InputQueue>();
+            queue.read_keyboard(window.keyboard(), &keymap);
+            queue.read_mouse(&window.mouse());
+        }
 
-        if self.runstate == RunState::Running {
-            run_systems(&mut self.ecs);
-            player_input(self, window);
+        // Nothing tells us when the window has been resized, so the layout is fitted again whenever its size changes
+        let window_pixels = window.screen_size();
+        if window_pixels != self.window_pixels {
+            window.set_view(View::new(Rectangle::new_sized(window_pixels)));
+            self.screen_layout = ScreenLayout::fit(self.screen_layout.tile_size_pixels, window_pixels, &self.panels);
+            self.window_pixels = window_pixels;
+        }
+
+        if !self.step() {
+            window.close();
         }
 
         Ok(())
     }
 
-
     /// Draw stuff on the screen
     fn draw(&mut self, window: &mut Window) -> Result<()> {
-        window.clear(Color::WHITE)?;
-
-        let line_end = Vector::new(self.screen_layout.right_panel_origin_pixels.x, self.screen_layout.right_panel_origin_pixels.y + self.screen_layout.window_size.times(self.screen_layout.tile_size_pixels).y);
-
-        window.draw_ex(
-            &Line::new(self.screen_layout.right_panel_origin_pixels, line_end).with_thickness(2.0),
-            Col(Color::WHITE),
-            Transform::IDENTITY,
-            5
-        );
-
-        let line_end_2 = Vector::new(self.screen_layout.right_panel_origin_pixels.x, self.screen_layout.bottom_panel_origin_pixels.y); 
-
-        window.draw_ex(
-            &Line::new(self.screen_layout.bottom_panel_origin_pixels, line_end_2).with_thickness(2.0),
-            Col(Color::WHITE),
-            Transform::IDENTITY,
-            5
-        );
+        // The layout only covers whole tiles, so whatever is left over at the edges stays as dark as the map
+        window.clear(Color::BLACK)?;
 
-        window.draw(&Rectangle::new(self.screen_layout.bottom_panel_origin_pixels, self.screen_layout.bottom_panel_size_pixels), Col(Color::BLACK));
-        window.draw(&Rectangle::new(self.screen_layout.right_panel_origin_pixels, self.screen_layout.right_panel_size_pixels), Col(Color::BLACK));
+        // The renderer holds on to the tileset while the rest of the game is drawn, so it is lent out for the frame
+        let mut tilesets = std::mem::replace(&mut self.tilesets, Vec::new());
+        let result = render_screen(self, &mut render::WindowRenderer { window, tileset: &mut tilesets[self.tileset_index] });
+        self.tilesets = tilesets;
 
-        render_text(window, "From function!", self.screen_layout.bottom_panel_origin_pixels, 20.0, Color::WHITE)?;
+        result
+    }
+}
 
-        if self.runstate == RunState::Paused {
-            render_text(window, "Paused", self.screen_layout.right_panel_origin_pixels, 20.0, Color::WHITE)?;
+/// Plays the game in the terminal it was started from instead of a window, for playing over SSH. It runs at the
+/// same rate as the window does, and is laid out the same way with a character cell for every tile.
+fn run_in_terminal() {
+    let mut terminal = match terminal::Terminal::open() {
+        Ok(terminal) => terminal,
+        Err(error) => {
+            eprintln!("Could not take over the terminal: {}", error);
+            return;
+        }
+    };
+    // stdout is the screen now, messages only go to the message panel
+    messages::stop_echoing();
+    let mut game = Game::new().expect("Could not set up the game.");
+    let tile_size = Vector::new(terminal::CELL_PIXELS.0, terminal::CELL_PIXELS.1);
+    let frame = std::time::Duration::from_millis(1000 / 60);
+    let mut frames = 0;
+
+    game.screen_layout = ScreenLayout::fit(tile_size, terminal.size_pixels(), &game.panels.for_terminal());
+
+    loop {
+        if terminal.resized() {
+            game.screen_layout = ScreenLayout::fit(tile_size, terminal.size_pixels(), &game.panels.for_terminal());
         }
 
-        let positions = self.ecs.read_storage::<components::Position>();
-        let renderables = self.ecs.read_storage::<components::Renderable>();
-
-        let map = self.ecs.fetch::<map::Map>();
-        let player_pos = self.ecs.fetch::<components::PlayerPosition>();
-        //println!("{}", Vector::new(player_pos.x, player_pos.y));
-
-        let tileset = &mut self.tileset;
-        let offset_px = self.screen_layout.screen_origin.times(self.screen_layout.tile_size_pixels);
-        let tile_pixels = self.screen_layout.tile_size_pixels;
-        let screen_layout = &self.screen_layout;
-
-        // render everything but tiles
-        tileset.execute(|tileset| {
-            for (pos, render) in (&positions, &renderables).join() {
-                let position = Vector::new(pos.x, pos.y);
-                let idx = map.point2d_to_index(rltk::Point::new(pos.x, pos.y));
-
-                let visible = map.visible_map[idx];
-                let revealed = map.revealed_map[idx];
-
-                let mapped_position = camera_translate(Vector::new(player_pos.x, player_pos.y), position, Vector::new(map.width, map.height), screen_layout);
-                let px_pos = offset_px + mapped_position.times(tile_pixels);
-
-                if !should_render(screen_layout.screen_origin + mapped_position, screen_layout) {
-                    continue;
-                }
-
-                if visible {
-                    if let Some(image) = tileset.get(&render.glyph) {
-                        window.draw(
-                            &Rectangle::new(px_pos, image.area().size()),
-                            Blended(&image, render.color),
-                        );
-                    }
-                } else if revealed && !visible {
-                    if let Some(image) = tileset.get(&render.glyph) {
-                        window.draw(
-                            &Rectangle::new(px_pos, image.area().size()),
-                            Blended(&image, Color::BLACK.with_alpha(0.5)),
-                        );
-                    }
-                } else {
-                    window.draw(
-                        &Rectangle::new(px_pos, screen_layout.tile_size_pixels),
-                        Color::BLACK,
-                    );
-                }
+        {
+            let keymap = game.ecs.fetch::<input::Keymap>();
+            let mut queue = game.ecs.write_resource::<input::InputQueue>();
+            if !terminal.read_keys(&mut queue, &keymap) {
+                break;
             }
+        }
 
-            Ok(())
-        })?;
-
-        /*
-        let full_health_width_px = 100.0;
-        let current_health_width_px = (50 as f32 / 100 as f32) * full_health_width_px;
-
-        let health_bar_pos_px = Vector::new(screen_width_tiles * TILE_EDGE_PIXELS, 0.0);
-        let mana_bar_pos_px = Vector::new(screen_width_tiles * TILE_EDGE_PIXELS, -TILE_EDGE_PIXELS);
+        if !game.step() {
+            break;
+        }
 
-        render_bar(window, Color::RED, current_health_width_px, health_bar_pos_px, full_health_width_px, tile_size_px)?;
-        render_bar(window, Color::BLUE, current_health_width_px, mana_bar_pos_px, full_health_width_px, tile_size_px)?;
-        */
+        if frames % TERMINAL_DRAW_FRAMES == 0 {
+            terminal.clear();
+            if let Err(error) = render_screen(&game, &mut terminal) {
+                eprintln!("Could not draw the screen: {:?}", error);
+                break;
+            }
+            if let Err(error) = terminal.present() {
+                eprintln!("Could not write to the terminal: {}", error);
+                break;
+            }
+        }
 
-        Ok(())
+        frames += 1;
+        std::thread::sleep(frame);
     }
 }
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--terminal") {
+        run_in_terminal();
+        return;
+    }
+
     // NOTE: Set HIDPI to 1.0 to get pixel-perfect rendering.
     // Otherwise the window resizes to whatever value the OS sets and
     // scales the contents.
@@ -465,6 +1664,8 @@ fn main() {
     let settings = Settings {
         // If the graphics do need to be scaled (e.g. using `with_center`), blur them. This looks better with fonts.
         scale: quicksilver::graphics::ImageScaleStrategy::Blur,
+        // Keep drawing at one pixel per pixel when the window is resized, the layout fills whatever space there is
+        resize: quicksilver::graphics::ResizeStrategy::Maintain,
         ..Default::default()
     };
 
@@ -472,3 +1673,83 @@ fn main() {
                 Vector::new(WINDOW_WIDTH_TILES * TILE_EDGE_PIXELS, WINDOW_HEIGHT_TILES * TILE_EDGE_PIXELS), 
                 settings);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_game() -> Game {
+        <Game as State>::new().expect("Unable to start a game")
+    }
+
+    fn inject(game: &Game, action: input::Action) {
+        game.ecs.write_resource::<input::InputQueue>().inject(action);
+    }
+
+    #[test]
+    fn layout_mistakes_are_reported() {
+        let sizes = PanelSizes::parse(DEFAULT_LAYOUT_RAW).unwrap();
+        assert_eq!((sizes.right, sizes.bottom), (8, 4));
+
+        assert!(PanelSizes::parse("right_panel | wide").is_err());
+        assert!(PanelSizes::parse("right_panel | -1").is_err());
+        assert!(PanelSizes::parse("left_panel | 2").err().unwrap().contains("left_panel"));
+    }
+
+    #[test]
+    fn injected_actions_toggle_the_unit_panel() {
+        let mut game = new_game();
+
+        inject(&game, input::Action::UnitInfo);
+        player_input(&mut game);
+        assert!(game.show_unit_info);
+
+        inject(&game, input::Action::Wait);
+        player_input(&mut game);
+        assert!(game.show_unit_info);
+
+        inject(&game, input::Action::UnitInfo);
+        player_input(&mut game);
+        assert!(!game.show_unit_info);
+    }
+
+    #[test]
+    fn only_one_overlay_is_shown() {
+        let mut game = new_game();
+
+        inject(&game, input::Action::Inventory);
+        player_input(&mut game);
+        assert!(game.show_inventory);
+
+        inject(&game, input::Action::UnitInfo);
+        player_input(&mut game);
+        assert!(game.show_unit_info);
+        assert!(!game.show_inventory);
+
+        inject(&game, input::Action::Inventory);
+        player_input(&mut game);
+        assert!(game.show_inventory);
+        assert!(!game.show_unit_info);
+    }
+
+    #[test]
+    fn injected_pause_toggles_the_game() {
+        let mut game = new_game();
+
+        inject(&game, input::Action::Pause);
+        assert!(game_input(&mut game));
+        assert!(game.runstate == RunState::Paused);
+
+        inject(&game, input::Action::Pause);
+        assert!(game_input(&mut game));
+        assert!(game.runstate == RunState::Running);
+    }
+
+    #[test]
+    fn injected_quit_leaves_the_game() {
+        let mut game = new_game();
+
+        inject(&game, input::Action::Quit);
+        assert!(!game_input(&mut game));
+    }
+}
@@ -4,9 +4,21 @@ use specs::prelude::*;
 use rltk::{ BaseMap, Algorithm2D, Point };
 
 use rand::Rng;
-use std::collections::HashMap;
+use std::collections::{ HashMap, VecDeque };
 
 use crate::components;
+use crate::factions::FactionKind;
+use crate::fluids;
+use crate::fluids::{ Fluid, FluidCell };
+use crate::geology;
+use crate::heat;
+
+/// What the map needs to know about a door for sight and pathing. Kept in step with the `Door` components.
+pub struct DoorState {
+    pub entity: Entity,
+    pub open: bool,
+    pub locked_for: &'static [FactionKind]
+}
 
 #[derive(Default)]
 pub struct Map {
@@ -17,6 +29,26 @@ pub struct Map {
     pub blocked : Vec<bool>,
     pub index_to_position_map: HashMap<usize, (i32, i32)>,
     pub position_to_index_map: HashMap<(i32, i32), usize>,
+    pub doors: HashMap<usize, DoorState>,
+    pub fluids: Vec<FluidCell>,
+    /// Rock holding water that floods out once it is dug into.
+    pub aquifers: HashMap<usize, Fluid>,
+    /// Breached aquifers and magma vents, which keep their tile topped up.
+    pub fluid_sources: HashMap<usize, Fluid>,
+    /// How high the floor of each tile is. Fluids run downhill, see `fluids::slope_floor`.
+    pub elevation: Vec<u8>,
+    pub temperature: Vec<i32>,
+    /// Turns each tile has left to burn. Fires are fed by whatever is burning on the tile.
+    pub fire: Vec<u8>,
+    pub smoke: Vec<u8>,
+    /// Tiles open to the sky.
+    pub surface: Vec<bool>,
+    /// Name of the material each tile's rock is made of, see `geology::lay_down_rock`.
+    pub rock: Vec<&'static str>,
+    /// Ore and gems still in the walls.
+    pub deposits: HashMap<usize, geology::Deposit>,
+    /// Whose locks A* has to respect while it is searching, see `step_towards_as`.
+    pub pathing_faction: Option<FactionKind>,
     pub width: i32,
     pub height: i32
 }
@@ -31,30 +63,203 @@ impl Map {
             blocked: vec![false; (width * height) as usize],
             index_to_position_map: HashMap::new(),
             position_to_index_map: HashMap::new(),
+            doors: HashMap::new(),
+            fluids: vec![FluidCell::dry(); (width * height) as usize],
+            aquifers: HashMap::new(),
+            fluid_sources: HashMap::new(),
+            elevation: vec![0; (width * height) as usize],
+            temperature: vec![heat::AMBIENT_TEMPERATURE; (width * height) as usize],
+            fire: vec![0; (width * height) as usize],
+            smoke: vec![0; (width * height) as usize],
+            surface: vec![false; (width * height) as usize],
+            rock: vec![""; (width * height) as usize],
+            deposits: HashMap::new(),
+            pathing_faction: None,
             width: width,
             height: height
         }
     }
 
+    pub fn xy_idx(&self, x: i32, y: i32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && x < self.width && y >= 0 && y < self.height
+    }
+
+    /// Returns the first step of an A* path from `start` towards `target`, if there is one.
+    /// The target tile itself is allowed to be blocked, since it is usually occupied by whatever we are heading for.
+    pub fn step_towards(&mut self, start: (i32, i32), target: (i32, i32)) -> Option<(i32, i32)> {
+        self.step_towards_as(start, target, None)
+    }
+
+    /// Like `step_towards`, but routes around doors that are locked to the given faction.
+    pub fn step_towards_as(&mut self, start: (i32, i32), target: (i32, i32), faction: Option<FactionKind>) -> Option<(i32, i32)> {
+        let start_idx = self.xy_idx(start.0, start.1);
+        let target_idx = self.xy_idx(target.0, target.1);
+
+        let target_blocked = self.blocked[target_idx];
+        self.blocked[target_idx] = false;
+        self.pathing_faction = faction;
+        let path = rltk::a_star_search(start_idx as i32, target_idx as i32, &mut *self);
+        self.pathing_faction = None;
+        self.blocked[target_idx] = target_blocked;
+
+        if path.success && path.steps.len() > 1 && path.steps[1] != target_idx {
+            let step = path.steps[1] as i32;
+            return Some((step % self.width, step / self.width));
+        }
+
+        None
+    }
+
+    /// Like `step_towards`, but will also take the final step onto the target if it is free.
+    pub fn step_onto(&mut self, start: (i32, i32), target: (i32, i32)) -> Option<(i32, i32)> {
+        self.step_onto_as(start, target, None)
+    }
+
+    pub fn step_onto_as(&mut self, start: (i32, i32), target: (i32, i32), faction: Option<FactionKind>) -> Option<(i32, i32)> {
+        if let Some(step) = self.step_towards_as(start, target, faction) {
+            return Some(step);
+        }
+
+        // Every other step is orthogonal, so the last one is too
+        let orthogonal = (target.0 - start.0).abs() + (target.1 - start.1).abs() == 1;
+        let target_idx = self.xy_idx(target.0, target.1);
+        if orthogonal && !self.blocked[target_idx] && !self.locked_out(target_idx, faction) {
+            return Some(target);
+        }
+
+        None
+    }
+
     pub fn populate_blocked(&mut self) {
         for (i, tile) in self.glyph_map.iter_mut().enumerate() {
             self.blocked[i] = *tile == '#';
         }
     }
 
+    /// A short description of the terrain at a tile, like "Granite wall" or "Floor, water 3 deep".
+    pub fn describe_tile(&self, idx: usize) -> String {
+        let rock = self.rock[idx];
+
+        let mut description = if let Some(door) = self.doors.get(&idx) {
+            if door.open { "Open door".to_string() } else { "Closed door".to_string() }
+        } else {
+            match (self.glyph_map[idx], self.deposits.get(&idx)) {
+                ('#', Some(deposit)) => format!("{} in {}", deposit.material, rock.to_lowercase()),
+                ('#', None) if !rock.is_empty() => format!("{} wall", rock),
+                ('#', None) => "Wall".to_string(),
+                (',', _) => "Paved floor".to_string(),
+                _ if self.surface[idx] => "Open ground".to_string(),
+                _ => "Floor".to_string()
+            }
+        };
+
+        let cell = self.fluids[idx];
+        if cell.depth > 0 {
+            let fluid = if cell.kind == Fluid::Water { "water" } else { "magma" };
+            description.push_str(&format!(", {} {} deep", fluid, cell.depth));
+        }
+        if self.fire[idx] > 0 {
+            description.push_str(", on fire");
+        }
+
+        description
+    }
+
+    /// First step on the shortest walk to the nearest tile nobody has seen yet, walking only over tiles that have been seen.
+    /// `None` once everything within reach has been explored.
+    pub fn step_towards_unexplored(&self, start: (i32, i32), faction: Option<FactionKind>) -> Option<(i32, i32)> {
+        let start_idx = self.xy_idx(start.0, start.1);
+        let mut came_from: HashMap<usize, usize> = HashMap::new();
+        let mut frontier = VecDeque::new();
+
+        came_from.insert(start_idx, start_idx);
+        frontier.push_back(start_idx);
+
+        while let Some(idx) = frontier.pop_front() {
+            let (x, y) = (idx as i32 % self.width, idx as i32 / self.width);
+
+            for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter().cloned() {
+                if !self.in_bounds(nx, ny) {
+                    continue;
+                }
+
+                let next = self.xy_idx(nx, ny);
+                if came_from.contains_key(&next) {
+                    continue;
+                }
+
+                if !self.revealed_map[next] {
+                    if idx == start_idx {
+                        return Some((nx, ny));
+                    }
+
+                    // Walk back to the step taken from the start
+                    let mut step = idx;
+                    while came_from[&step] != start_idx {
+                        step = came_from[&step];
+                    }
+                    return Some((step as i32 % self.width, step as i32 / self.width));
+                }
+
+                if self.glyph_map[next] == '#' || self.fluid_blocks(next) || self.locked_out(next, faction) {
+                    continue;
+                }
+
+                came_from.insert(next, idx);
+                frontier.push_back(next);
+            }
+        }
+
+        None
+    }
+
+    pub fn door_closed(&self, idx: usize) -> bool {
+        self.doors.get(&idx).map_or(false, |door| !door.open)
+    }
+
+    pub fn locked_out(&self, idx: usize, faction: Option<FactionKind>) -> bool {
+        match (self.doors.get(&idx), faction) {
+            (Some(door), Some(faction)) => door.locked_for.contains(&faction),
+            _ => false
+        }
+    }
+
+    /// Magma and deep water can't be walked into.
+    pub fn fluid_blocks(&self, idx: usize) -> bool {
+        let cell = self.fluids[idx];
+        cell.depth > 0 && (cell.kind == Fluid::Magma || cell.depth >= fluids::IMPASSABLE_WATER_DEPTH)
+    }
+
+    /// Whether something can step into a tile at all. Deep water slows each unit down separately, see `fluids::wade`.
+    pub fn can_enter(&self, idx: usize) -> bool {
+        !self.fluid_blocks(idx)
+    }
+
     fn is_exit_valid(&self, x:i32, y:i32) -> bool {
         if x < 1 || x > self.width - 1 || y < 1 || y > self.height - 1 { 
             return false; 
         }
 
         let idx = self.position_to_index_map[&(x, y)];
-        !self.blocked[idx]
+        !self.blocked[idx] && !self.fluid_blocks(idx) && !self.locked_out(idx, self.pathing_faction)
+    }
+
+    /// Closed doors cost an extra turn to open on the way through, and wading is slower than walking.
+    fn exit_cost(&self, idx: usize) -> f32 {
+        let door = if self.door_closed(idx) { 1.0 } else { 0.0 };
+        let wading = if self.fluids[idx].depth >= fluids::WADING_DEPTH { 1.0 } else { 0.0 };
+
+        1.0 + door + wading
     }
 }
 
 impl BaseMap for Map {
     fn is_opaque(&self, idx: usize) -> bool {
-        self.glyph_map[idx] == '#' 
+        self.glyph_map[idx] == '#' || self.door_closed(idx)
     }
 
     fn get_available_exits(&self, idx:usize) -> Vec<(usize, f32)> {
@@ -63,10 +268,10 @@ impl BaseMap for Map {
         let y = idx as i32 / self.width;
         let w = self.width as usize;
     
-        if self.is_exit_valid(x-1, y) { exits.push((idx-1, 1.0)) };
-        if self.is_exit_valid(x+1, y) { exits.push((idx+1, 1.0)) };
-        if self.is_exit_valid(x, y-1) { exits.push((idx-w, 1.0)) };
-        if self.is_exit_valid(x, y+1) { exits.push((idx+w, 1.0)) };
+        if self.is_exit_valid(x-1, y) { exits.push((idx-1, self.exit_cost(idx-1))) };
+        if self.is_exit_valid(x+1, y) { exits.push((idx+1, self.exit_cost(idx+1))) };
+        if self.is_exit_valid(x, y-1) { exits.push((idx-w, self.exit_cost(idx-w))) };
+        if self.is_exit_valid(x, y+1) { exits.push((idx+w, self.exit_cost(idx+w))) };
     
         exits
     }
@@ -117,7 +322,6 @@ pub fn generate_map_new(ecs: &mut World, size: Vector) {
     
     let mut map = Map::new(size.x as i32, size.y as i32); 
     let mut rng = rand::thread_rng();
-    let mut index = 0;
 
     for x in 0..width {
         for y in 0..height {
@@ -131,6 +335,7 @@ pub fn generate_map_new(ecs: &mut World, size: Vector) {
             let tile = generate_tile(ecs, glyph, x as i32, y as i32);
             
             let coords = (x as i32, y as i32);
+            let index = map.xy_idx(coords.0, coords.1);
             map.tiles.insert(coords, tile);
             map.glyph_map[index] = glyph;
 
@@ -138,8 +343,6 @@ pub fn generate_map_new(ecs: &mut World, size: Vector) {
             map.position_to_index_map.insert(coords, index as usize);
             map.revealed_map[index] = false;
             map.visible_map[index] = false;
-
-            index += 1;
         }
     }
 
@@ -205,4 +408,54 @@ pub fn apply_ca(ecs: &World, map: &mut Map) {
             render.glyph = '.';
         }
     }
-}
\ No newline at end of file
+}
+/// Tiles on a straight line between two points, both ends included.
+pub fn line(start: (i32, i32), end: (i32, i32)) -> Vec<(i32, i32)> {
+    let (mut x, mut y) = start;
+    let dx = (end.0 - x).abs();
+    let dy = -(end.1 - y).abs();
+    let step_x = if x < end.0 { 1 } else { -1 };
+    let step_y = if y < end.1 { 1 } else { -1 };
+    let mut error = dx + dy;
+    let mut points = Vec::new();
+
+    loop {
+        points.push((x, y));
+        if (x, y) == end {
+            break;
+        }
+
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+
+    points
+}
+
+/// Changes the terrain at a tile, keeping the tile entity, the glyph map and the blocked map in step.
+/// Callers should dirty any viewsheds that might be affected.
+pub fn set_tile_glyph(map: &mut Map, renderables: &mut WriteStorage<components::Renderable>, x: i32, y: i32, glyph: char) {
+    if let Some(tile) = map.tiles.get(&(x, y)) {
+        if let Some(render) = renderables.get_mut(*tile) {
+            // Only untouched rock is tinted by what it is made of
+            render.glyph = glyph;
+            render.color = Color::BLACK;
+        }
+    }
+
+    let idx = map.xy_idx(x, y);
+    map.glyph_map[idx] = glyph;
+    map.blocked[idx] = glyph == '#';
+
+    if glyph == '#' {
+        map.fluids[idx] = FluidCell::dry();
+        map.fluid_sources.remove(&idx);
+    }
+}
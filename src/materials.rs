@@ -0,0 +1,63 @@
+use specs::prelude::*;
+
+use crate::components;
+use crate::raws;
+use crate::skills::QualityTier;
+
+/// Hauling anything heavier than this many kilograms slows the hauler down to a step every other turn.
+pub static HEAVY_LOAD: f32 = 40.0;
+/// Density of things not made of any particular material, like food and drink.
+pub static DEFAULT_DENSITY: i32 = 1000;
+/// Weapons and armour made of something this hard fight exactly as well as their base rating.
+pub static BASE_HARDNESS: i32 = 5;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum MaterialKind { Soil, Stone, Ore, Metal, Gem, Wood, Leather, Plant }
+
+impl MaterialKind {
+    pub fn from_name(name: &str) -> Option<MaterialKind> {
+        match name {
+            "soil" => Some(MaterialKind::Soil),
+            "stone" => Some(MaterialKind::Stone),
+            "ore" => Some(MaterialKind::Ore),
+            "metal" => Some(MaterialKind::Metal),
+            "gem" => Some(MaterialKind::Gem),
+            "wood" => Some(MaterialKind::Wood),
+            "leather" => Some(MaterialKind::Leather),
+            "plant" => Some(MaterialKind::Plant),
+            _ => None
+        }
+    }
+}
+
+pub fn weight(template: &raws::ItemTemplate, material: Option<&raws::Material>) -> f32 {
+    template.volume * material.map_or(DEFAULT_DENSITY, |m| m.density) as f32 / 1000.0
+}
+
+pub fn value(template: &raws::ItemTemplate, material: Option<&raws::Material>, quality: QualityTier) -> i32 {
+    (template.value as f32 * material.map_or(1, |m| m.value) as f32 * quality.value_multiplier()).round() as i32
+}
+
+/// Damage for weapons, defense for armour. Harder materials and better craftsmanship improve on the base rating.
+pub fn rating(base: i32, material: Option<&raws::Material>, quality: QualityTier) -> i32 {
+    let hardness = material.map_or(BASE_HARDNESS, |m| m.hardness);
+    i32::max(1, base + (hardness - BASE_HARDNESS) / 2 + quality.bonus())
+}
+
+/// What a crafted item ends up made of. It takes after its ingredient if that is the same kind of material as
+/// the item is normally made of, so granite makes granite mugs and pine makes pine planks.
+pub fn crafted_material<'a>(template: &'a raws::ItemTemplate, ingredient: Option<&'a raws::Material>) -> Option<&'a raws::Material> {
+    match (template.material.as_ref(), ingredient) {
+        (Some(usual), Some(used)) if usual.kind == used.kind => Some(used),
+        (usual, _) => usual
+    }
+}
+
+/// The damage of the best weapon and the defense of the best armour someone is carrying.
+pub fn equipment(owner: Entity, carried: &ReadStorage<components::CarriedBy>, weapons: &ReadStorage<components::Weapon>,
+                 armour: &ReadStorage<components::Armour>) -> (i32, i32) {
+    let damage = (carried, weapons).join().filter(|(c, _)| c.owner == owner).map(|(_, w)| w.damage).max().unwrap_or(0);
+    let defense = (carried, armour).join().filter(|(c, _)| c.owner == owner).map(|(_, a)| a.defense).max().unwrap_or(0);
+
+    (damage, defense)
+}
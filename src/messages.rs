@@ -0,0 +1,47 @@
+use quicksilver::prelude::*;
+
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Mutex;
+
+use crate::render::Renderer;
+
+/// How many of the latest messages are kept for the message panel.
+static KEPT_MESSAGES: usize = 100;
+/// Height of a line in the message panel, in pixels.
+pub static MESSAGE_ROW_PIXELS: f32 = 18.0;
+
+/// What the game has had to say lately, oldest first. It is kept here rather than in the `World` so that loaders
+/// and helpers that don't have one can post to it too.
+static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/// Messages are printed as well as kept, except while the game is drawn in the terminal.
+static ECHO: AtomicBool = AtomicBool::new(true);
+
+/// Tells the player something. It shows up in the message panel, and on stdout unless the game is drawn in the terminal.
+pub fn post(message: String) {
+    if ECHO.load(Ordering::Relaxed) {
+        println!("{}", message);
+    }
+
+    let mut messages = MESSAGES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    messages.push(message);
+    if messages.len() > KEPT_MESSAGES {
+        messages.remove(0);
+    }
+}
+
+/// Stops printing messages, for when the terminal the game was started from is where it is drawn.
+pub fn stop_echoing() {
+    ECHO.store(false, Ordering::Relaxed);
+}
+
+/// Draws as many of the latest messages as fit in an area, newest at the bottom.
+pub fn render_messages(renderer: &mut dyn Renderer, area: Rectangle) -> Result<()> {
+    let rows = (area.size.y / MESSAGE_ROW_PIXELS).floor().max(0.0) as usize;
+    let messages = MESSAGES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    for (row, message) in messages[messages.len().saturating_sub(rows)..].iter().enumerate() {
+        renderer.text(message, area.pos + Vector::new(4.0, row as f32 * MESSAGE_ROW_PIXELS), 14.0, Color::WHITE)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+
+use crate::camera;
+use crate::components;
+use crate::factions;
+use crate::fluids;
+use crate::map;
+use crate::render::Renderer;
+use crate::ScreenLayout;
+
+static MINIMAP_MARGIN_PIXELS: f32 = 10.0;
+/// Room left under the minimap for the date.
+static MINIMAP_DATE_PIXELS: f32 = 50.0;
+/// Below this many pixels per map tile the minimap is too small to be any use, so it isn't shown.
+static MINIMAP_MIN_SCALE: f32 = 0.5;
+
+/// Where the minimap sits in the right-hand panel, just above the date, and how many pixels a map tile takes up on it.
+/// The whole map has to fit in the panel, so there is no minimap when the panel is too small for it.
+fn minimap_area(screen_layout: &ScreenLayout, map_width: i32, map_height: i32) -> Option<(Rectangle, f32)> {
+    let room = screen_layout.right_panel_size_pixels - Vector::new(2.0 * MINIMAP_MARGIN_PIXELS, MINIMAP_MARGIN_PIXELS + MINIMAP_DATE_PIXELS);
+    let scale = (room.x / map_width as f32).min(room.y / map_height as f32);
+    if scale < MINIMAP_MIN_SCALE {
+        return None;
+    }
+
+    let size = Vector::new(map_width as f32 * scale, map_height as f32 * scale);
+    let origin = screen_layout.right_panel_origin_pixels + Vector::new(MINIMAP_MARGIN_PIXELS, screen_layout.right_panel_size_pixels.y - MINIMAP_DATE_PIXELS - size.y);
+
+    Some((Rectangle::new(origin, size), scale))
+}
+
+/// The map tile under a pixel of the minimap, if the pixel is on it.
+pub fn minimap_tile(pixel: Vector, ecs: &World, screen_layout: &ScreenLayout) -> Option<(i32, i32)> {
+    let map = ecs.fetch::<map::Map>();
+    let (area, scale) = minimap_area(screen_layout, map.width, map.height)?;
+
+    if !area.contains(pixel) {
+        return None;
+    }
+
+    let offset = pixel - area.pos;
+    let (x, y) = ((offset.x / scale) as i32, (offset.y / scale) as i32);
+    if map.in_bounds(x, y) { Some((x, y)) } else { None }
+}
+
+/// The whole map scaled down: what has been seen, who can be seen now, workshops, doors and the marked location,
+/// and a frame around the part of the map in view.
+pub fn render_minimap(renderer: &mut dyn Renderer, ecs: &World, screen_layout: &ScreenLayout, marked: Option<(i32, i32)>) -> Result<()> {
+    let map = ecs.fetch::<map::Map>();
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<components::Position>();
+    let players = ecs.read_storage::<components::Player>();
+    let factions = ecs.read_storage::<components::Faction>();
+    let combat_stats = ecs.read_storage::<components::CombatStats>();
+    let workshops = ecs.read_storage::<components::Workshop>();
+    let faction_table = ecs.fetch::<factions::FactionTable>();
+    let selected = ecs.fetch::<components::SelectedUnit>();
+
+    let (area, scale) = match minimap_area(screen_layout, map.width, map.height) {
+        Some(area) => area,
+        None => return Ok(())
+    };
+    let tile = Vector::new(scale, scale);
+    let at = |x: i32, y: i32| area.pos + Vector::new(x as f32 * scale, y as f32 * scale);
+
+    renderer.fill(area, Color::from_rgba(15, 15, 15, 1.0))?;
+
+    for (idx, _revealed) in map.revealed_map.iter().enumerate().filter(|(_, revealed)| **revealed) {
+        let cell = map.fluids[idx];
+        let colour = if cell.depth > 0 {
+            fluids::fluid_colour(cell)
+        } else if map.glyph_map[idx] == '#' {
+            Color::from_rgba(120, 120, 120, 1.0)
+        } else {
+            Color::from_rgba(55, 50, 45, 1.0)
+        };
+        let colour = if map.visible_map[idx] { colour } else { colour.multiply(Color::from_rgba(150, 150, 150, 1.0)) };
+
+        renderer.fill(Rectangle::new(at(idx as i32 % map.width, idx as i32 / map.width), tile), colour)?;
+    }
+
+    for (_workshop, pos) in (&workshops, &positions).join() {
+        renderer.fill(Rectangle::new(at(pos.x - 1, pos.y - 1), tile * 3.0), Color::from_rgba(110, 70, 30, 0.8))?;
+    }
+    for (idx, _door) in map.doors.iter().filter(|(idx, _)| map.revealed_map[**idx]) {
+        renderer.fill(Rectangle::new(at(*idx as i32 % map.width, *idx as i32 / map.width), tile), Color::from_rgba(170, 120, 70, 1.0))?;
+    }
+    if let Some((x, y)) = marked {
+        renderer.fill(Rectangle::new(at(x, y) - tile, tile * 3.0), Color::PURPLE)?;
+    }
+
+    // Creatures show up while they can be seen: our own in cyan, hostiles in red and anyone else in yellow
+    let own_faction = selected.entity.and_then(|entity| factions.get(entity)).map(|f| f.kind);
+    for (entity, pos, faction, stats) in (&entities, &positions, &factions, &combat_stats).join() {
+        if stats.hp <= 0 || !map.visible_map[map.xy_idx(pos.x, pos.y)] {
+            continue;
+        }
+
+        let colour = if selected.entity == Some(entity) {
+            Color::WHITE
+        } else if players.get(entity).is_some() {
+            Color::CYAN
+        } else if own_faction.map_or(false, |own| faction_table.reaction(own, faction.kind) == factions::Reaction::Attack) {
+            Color::RED
+        } else {
+            Color::YELLOW
+        };
+
+        renderer.fill(Rectangle::new(at(pos.x, pos.y) - tile * 0.5, tile * 2.0), colour)?;
+    }
+
+    let map_size = Vector::new(map.width, map.height);
+    let view_size = screen_layout.view_size(camera::camera_zoom(ecs));
+    let view = Rectangle::new(at(0, 0) + camera::camera_translation(camera::camera_focus(ecs), map_size, view_size) * scale, view_size * scale);
+    let corners = [view.pos, view.pos + Vector::new(view.size.x, 0.0), view.pos + view.size, view.pos + Vector::new(0.0, view.size.y)];
+    for side in 0..4 {
+        renderer.line(corners[side], corners[(side + 1) % 4], 1.0, Color::WHITE)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+
+use crate::camera;
+use crate::components;
+use crate::construction;
+use crate::doors;
+use crate::input;
+use crate::jobs;
+use crate::map;
+use crate::messages;
+use crate::minimap;
+use crate::order_move_to;
+use crate::render::Renderer;
+use crate::workshops;
+use crate::Game;
+use crate::ScreenLayout;
+
+/// Something that can be done to a tile from its right-click menu.
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum ContextAction { Travel, Dig, Deconstruct, CycleLock, PlaceWorkshop, PlaceBlueprint }
+
+pub struct ContextMenu {
+    tile: (i32, i32),
+    /// Top left corner of the menu, in pixels.
+    origin: Vector,
+    options: Vec<ContextAction>
+}
+
+static CONTEXT_MENU_WIDTH: f32 = 200.0;
+static CONTEXT_MENU_ROW: f32 = 20.0;
+
+/// The map tile under a pixel of the window, if the pixel is over the map view.
+fn pixel_to_tile(pixel: Vector, ecs: &World, screen_layout: &ScreenLayout) -> Option<(i32, i32)> {
+    let zoom = camera::camera_zoom(ecs);
+    let view_size = screen_layout.view_size(zoom);
+    let tile_pixels = screen_layout.tile_size_pixels * zoom;
+    let offset = pixel - screen_layout.screen_origin.times(screen_layout.tile_size_pixels);
+
+    let on_screen = Vector::new((offset.x / tile_pixels.x).floor(), (offset.y / tile_pixels.y).floor());
+    if !camera::should_render(on_screen, view_size) {
+        return None;
+    }
+
+    let map = ecs.fetch::<map::Map>();
+    let tile = camera::camera_untranslate(camera::camera_focus(ecs), on_screen, Vector::new(map.width, map.height), view_size);
+    let (x, y) = (tile.x as i32, tile.y as i32);
+
+    if map.in_bounds(x, y) { Some((x, y)) } else { None }
+}
+
+impl ContextAction {
+    fn label(&self, game: &Game) -> String {
+        match self {
+            ContextAction::Travel => "Travel here".to_string(),
+            ContextAction::Dig => "Dig".to_string(),
+            ContextAction::Deconstruct => "Take down".to_string(),
+            ContextAction::CycleLock => "Change lock".to_string(),
+            ContextAction::PlaceWorkshop => format!("Place {}", workshops::WORKSHOP_KINDS[game.workshop_kind].name()),
+            ContextAction::PlaceBlueprint => {
+                let kind = construction::CONSTRUCTION_KINDS[game.build_mode.unwrap_or(0)];
+                format!("Build {}", kind.name().to_lowercase())
+            }
+        }
+    }
+}
+
+/// What can be done with a tile, for its right-click menu.
+fn context_options(game: &Game, x: i32, y: i32) -> Vec<ContextAction> {
+    let map = game.ecs.fetch::<map::Map>();
+    let constructed = game.ecs.read_storage::<components::Constructed>();
+    let idx = map.xy_idx(x, y);
+    let mut options = Vec::new();
+
+    if !map.revealed_map[idx] {
+        return options;
+    }
+
+    let wall = map.glyph_map[idx] == '#';
+    let built = construction::construction_at(&game.ecs, x, y).map_or(false, |entity| constructed.get(entity).is_some()) ||
+        map.tiles.get(&(x, y)).map_or(false, |tile| constructed.get(*tile).is_some());
+
+    if !wall && !map.fluid_blocks(idx) {
+        options.push(ContextAction::Travel);
+    }
+    if wall {
+        options.push(ContextAction::Dig);
+    }
+    if built {
+        options.push(ContextAction::Deconstruct);
+    }
+    if map.doors.contains_key(&idx) {
+        options.push(ContextAction::CycleLock);
+    }
+    if !wall {
+        options.push(ContextAction::PlaceWorkshop);
+        if game.build_mode.is_some() {
+            options.push(ContextAction::PlaceBlueprint);
+        }
+    }
+
+    options
+}
+
+fn apply_context_action(game: &mut Game, action: ContextAction, x: i32, y: i32) {
+    match action {
+        ContextAction::Travel => order_move_to(&game.ecs, x, y),
+        ContextAction::Dig => {
+            game.ecs.write_resource::<jobs::JobBoard>().designate(jobs::JobKind::Dig, x, y);
+        }
+        ContextAction::Deconstruct => {
+            construction::designate_deconstruction(&game.ecs, x, y);
+        }
+        ContextAction::CycleLock => doors::cycle_lock(&game.ecs, x, y),
+        ContextAction::PlaceWorkshop => {
+            let kind = workshops::WORKSHOP_KINDS[game.workshop_kind];
+            if !workshops::place_workshop(&mut game.ecs, kind, x, y) {
+                messages::post(format!("There is no room for a {} here", kind.name()));
+            }
+        }
+        ContextAction::PlaceBlueprint => {
+            if let Some(kind) = game.build_mode {
+                let kind = construction::CONSTRUCTION_KINDS[kind];
+                if !construction::place_blueprint(&mut game.ecs, kind, x, y) {
+                    messages::post(format!("A {} can't be built here", kind.name().to_lowercase()));
+                }
+            }
+        }
+    }
+}
+
+/// Left-clicking a tile sends the selected unit there, right-clicking opens a menu of what can be done with it.
+/// While a menu is open, the next left click picks from it or closes it.
+pub fn mouse_input(game: &mut Game) {
+    let clicks = game.ecs.write_resource::<input::InputQueue>().drain_clicks();
+
+    for (button, pixel) in clicks {
+        if let Some(menu) = game.context_menu.take() {
+            if button == MouseButton::Left {
+                let offset = pixel - menu.origin;
+                let row = (offset.y / CONTEXT_MENU_ROW).floor() as i32;
+
+                if offset.x >= 0.0 && offset.x < CONTEXT_MENU_WIDTH && row >= 0 && (row as usize) < menu.options.len() {
+                    apply_context_action(game, menu.options[row as usize], menu.tile.0, menu.tile.1);
+                }
+                continue;
+            }
+        }
+
+        if let Some((x, y)) = minimap::minimap_tile(pixel, &game.ecs, &game.screen_layout) {
+            if button == MouseButton::Left {
+                camera::jump_camera(&game.ecs, x, y);
+            }
+            continue;
+        }
+
+        let (x, y) = match pixel_to_tile(pixel, &game.ecs, &game.screen_layout) {
+            Some(tile) => tile,
+            None => continue
+        };
+
+        match button {
+            MouseButton::Left => {
+                if context_options(game, x, y).contains(&ContextAction::Travel) {
+                    order_move_to(&game.ecs, x, y);
+                }
+            }
+            MouseButton::Right => {
+                let options = context_options(game, x, y);
+                if !options.is_empty() {
+                    game.context_menu = Some(ContextMenu { tile: (x, y), origin: pixel, options });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Names the terrain under the mouse pointer, and whatever can be seen standing or lying on it.
+pub fn render_tooltip(renderer: &mut dyn Renderer, ecs: &World, screen_layout: &ScreenLayout) -> Result<()> {
+    let pixel = match ecs.fetch::<input::InputQueue>().mouse {
+        Some(pixel) => pixel,
+        None => return Ok(())
+    };
+    let (x, y) = match pixel_to_tile(pixel, ecs, screen_layout) {
+        Some(tile) => tile,
+        None => return Ok(())
+    };
+
+    let map = ecs.fetch::<map::Map>();
+    let idx = map.xy_idx(x, y);
+    if !map.revealed_map[idx] {
+        return Ok(());
+    }
+
+    let mut lines = vec![map.describe_tile(idx)];
+    if map.visible_map[idx] {
+        let names = ecs.read_storage::<components::Name>();
+        let positions = ecs.read_storage::<components::Position>();
+        let tiles = ecs.read_storage::<components::Tile>();
+
+        for (name, _pos, _not_tile) in (&names, &positions, !&tiles).join().filter(|(_, pos, _)| pos.x == x && pos.y == y) {
+            lines.push(name.name.clone());
+        }
+    }
+
+    let origin = pixel + Vector::new(16.0, 16.0);
+    renderer.fill(Rectangle::new(origin, (CONTEXT_MENU_WIDTH, CONTEXT_MENU_ROW * lines.len() as f32 + 4.0)), Color::BLACK.with_alpha(0.8))?;
+    for (row, line) in lines.iter().enumerate() {
+        renderer.text(line, origin + Vector::new(4.0, 2.0 + row as f32 * CONTEXT_MENU_ROW), 14.0, Color::WHITE)?;
+    }
+
+    Ok(())
+}
+
+pub fn render_context_menu(renderer: &mut dyn Renderer, game: &Game) -> Result<()> {
+    let menu = match &game.context_menu {
+        Some(menu) => menu,
+        None => return Ok(())
+    };
+
+    renderer.fill(Rectangle::new(menu.origin, (CONTEXT_MENU_WIDTH, CONTEXT_MENU_ROW * menu.options.len() as f32)), Color::BLACK)?;
+    for (row, option) in menu.options.iter().enumerate() {
+        renderer.text(&option.label(game), menu.origin + Vector::new(4.0, row as f32 * CONTEXT_MENU_ROW), 14.0, Color::WHITE)?;
+    }
+
+    Ok(())
+}
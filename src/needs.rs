@@ -0,0 +1,291 @@
+use specs::prelude::*;
+
+use crate::components;
+use crate::components::NeedKind;
+use crate::doors;
+use crate::fluids;
+use crate::map;
+use crate::messages;
+use crate::noise;
+use crate::scheduler;
+
+/// Below this fraction of the maximum a dwarf drops what it is doing to look after itself.
+pub static SEEK_THRESHOLD: f32 = 0.3;
+/// Turns between points of damage from starving or dehydration.
+pub static DEPRIVATION_DAMAGE_INTERVAL: u64 = 10;
+pub static BED_REST_PER_TURN: i32 = 10;
+pub static FLOOR_REST_PER_TURN: i32 = 4;
+
+fn below_threshold(current: i32, max: i32) -> bool {
+    (current as f32) < (max as f32) * SEEK_THRESHOLD
+}
+
+/// Status line for a need, e.g. "Hungry" or "Starving". Empty when the need is met.
+pub fn status(need: NeedKind, current: i32, max: i32) -> &'static str {
+    let (needy, critical) = match need {
+        NeedKind::Hunger => ("Hungry", "Starving"),
+        NeedKind::Thirst => ("Thirsty", "Dehydrated"),
+        NeedKind::Fatigue => ("Tired", "Exhausted"),
+    };
+
+    if current <= 0 {
+        critical
+    } else if below_threshold(current, max) {
+        needy
+    } else {
+        ""
+    }
+}
+
+/// Ticks hunger, thirst and fatigue down once per turn, hurts dwarves whose needs go unmet,
+/// and sends dwarves off to eat, drink or sleep when they cross the threshold.
+pub struct NeedsSystem {}
+
+impl<'a> System<'a> for NeedsSystem {
+    type SystemData = ( ReadExpect<'a, scheduler::Scheduler>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::Name>,
+                        ReadStorage<'a, components::Position>,
+                        WriteStorage<'a, components::Hunger>,
+                        WriteStorage<'a, components::Thirst>,
+                        WriteStorage<'a, components::Fatigue>,
+                        WriteStorage<'a, components::Asleep>,
+                        WriteStorage<'a, components::Seeking>,
+                        WriteStorage<'a, components::AssignedJob>,
+                        WriteStorage<'a, components::MoveOrder>,
+                        WriteStorage<'a, components::SufferDamage>,
+                        ReadStorage<'a, components::Edible>,
+                        ReadStorage<'a, components::Drinkable>,
+                        ReadStorage<'a, components::Bed>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (scheduler, entities, names, positions, mut hungers, mut thirsts, mut fatigues, mut asleep,
+             mut seeking, mut assigned_jobs, mut move_orders, mut inflict_damage, edibles, drinkables, beds) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        let deprivation_turn = scheduler.turn % DEPRIVATION_DAMAGE_INTERVAL == 0;
+
+        for (entity, hunger) in (&entities, &mut hungers).join() {
+            hunger.current = i32::max(0, hunger.current - 1);
+            if hunger.current == 0 && deprivation_turn {
+                components::SufferDamage::new_damage(&mut inflict_damage, entity, 1);
+            }
+        }
+
+        for (entity, thirst) in (&entities, &mut thirsts).join() {
+            thirst.current = i32::max(0, thirst.current - 1);
+            if thirst.current == 0 && deprivation_turn {
+                components::SufferDamage::new_damage(&mut inflict_damage, entity, 1);
+            }
+        }
+
+        let mut collapsed: Vec<Entity> = Vec::new();
+        let mut woken: Vec<Entity> = Vec::new();
+
+        for (entity, pos, fatigue) in (&entities, &positions, &mut fatigues).join() {
+            if asleep.get(entity).is_some() {
+                let in_bed = (&beds, &positions).join().any(|(_bed, bed_pos)| bed_pos.x == pos.x && bed_pos.y == pos.y);
+                let rest = if in_bed { BED_REST_PER_TURN } else { FLOOR_REST_PER_TURN };
+
+                fatigue.current = i32::min(fatigue.max, fatigue.current + rest);
+                if fatigue.current == fatigue.max {
+                    woken.push(entity);
+                }
+            } else {
+                fatigue.current = i32::max(0, fatigue.current - 1);
+                if fatigue.current == 0 {
+                    collapsed.push(entity);
+                }
+            }
+        }
+
+        for entity in woken {
+            asleep.remove(entity);
+            if let Some(name) = names.get(entity) {
+                messages::post(format!("{} wakes up", name.name));
+            }
+        }
+
+        for entity in collapsed {
+            if let Some(name) = names.get(entity) {
+                messages::post(format!("{} collapses from exhaustion", name.name));
+            }
+            asleep.insert(entity, components::Asleep{}).expect("Unable to insert sleep");
+            seeking.remove(entity);
+            assigned_jobs.remove(entity);
+            move_orders.remove(entity);
+        }
+
+        // Anything already being sought is spoken for
+        let mut claimed: Vec<Entity> = (&seeking).join().map(|s| s.target).collect();
+        let mut seekers: Vec<(Entity, components::Seeking)> = Vec::new();
+
+        for (entity, pos, _awake, _not_seeking) in (&entities, &positions, !&asleep, !&seeking).join() {
+            // Drinking comes before eating, which comes before sleeping
+            let need = if thirsts.get(entity).map_or(false, |t| below_threshold(t.current, t.max)) {
+                NeedKind::Thirst
+            } else if hungers.get(entity).map_or(false, |h| below_threshold(h.current, h.max)) {
+                NeedKind::Hunger
+            } else if fatigues.get(entity).map_or(false, |f| below_threshold(f.current, f.max)) {
+                NeedKind::Fatigue
+            } else {
+                continue;
+            };
+
+            let mut nearest: Option<(f32, Entity)> = None;
+
+            for (candidate, candidate_pos) in (&entities, &positions).join() {
+                let satisfies = match need {
+                    NeedKind::Hunger => edibles.get(candidate).is_some(),
+                    NeedKind::Thirst => drinkables.get(candidate).is_some(),
+                    NeedKind::Fatigue => beds.get(candidate).is_some(),
+                };
+
+                if !satisfies || claimed.contains(&candidate) {
+                    continue;
+                }
+
+                let distance = rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(pos.x, pos.y), rltk::Point::new(candidate_pos.x, candidate_pos.y));
+                if nearest.map_or(true, |(best, _)| distance < best) {
+                    nearest = Some((distance, candidate));
+                }
+            }
+
+            if let Some((_distance, target)) = nearest {
+                claimed.push(target);
+                seekers.push((entity, components::Seeking{ need, target }));
+            } else if need == NeedKind::Fatigue {
+                // No bed to be had, so sleep on the floor
+                seekers.push((entity, components::Seeking{ need, target: entity }));
+            }
+        }
+
+        for (entity, seek) in seekers {
+            assigned_jobs.remove(entity);
+            move_orders.remove(entity);
+            seeking.insert(entity, seek).expect("Unable to insert seeking");
+        }
+    }
+}
+
+/// Walks dwarves to whatever they are seeking and eats, drinks or sleeps once they get there.
+pub struct SeekingSystem {}
+
+impl<'a> System<'a> for SeekingSystem {
+    type SystemData = ( WriteExpect<'a, map::Map>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        WriteExpect<'a, noise::NoiseQueue>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::Name>,
+                        WriteStorage<'a, components::Position>,
+                        WriteStorage<'a, components::Viewshed>,
+                        WriteStorage<'a, components::Seeking>,
+                        WriteStorage<'a, components::Asleep>,
+                        WriteStorage<'a, components::Hunger>,
+                        WriteStorage<'a, components::Thirst>,
+                        ReadStorage<'a, components::Edible>,
+                        ReadStorage<'a, components::Drinkable>,
+                        ReadStorage<'a, components::Faction>,
+                        WriteStorage<'a, components::Door>,
+                        WriteStorage<'a, components::Wading>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut map, scheduler, mut noises, entities, names, mut positions, mut viewsheds, mut seeking, mut asleep,
+             mut hungers, mut thirsts, edibles, drinkables, factions, mut doors, mut wading) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        let targets: Vec<(Entity, Entity)> = (&entities, &seeking).join().map(|(entity, s)| (entity, s.target)).collect();
+        let mut finished: Vec<Entity> = Vec::new();
+
+        for (entity, target) in targets {
+            let need = match seeking.get(entity) {
+                Some(s) => s.need,
+                None => continue
+            };
+
+            let target_pos = match positions.get(target) {
+                Some(pos) if entities.is_alive(target) => (pos.x, pos.y),
+                _ => {
+                    // Somebody else got there first
+                    finished.push(entity);
+                    continue;
+                }
+            };
+
+            let pos = match positions.get_mut(entity) {
+                Some(pos) => pos,
+                None => continue
+            };
+
+            // Food and drink can be reached for from the next tile, a bed has to be climbed into
+            let distance = rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(pos.x, pos.y), rltk::Point::new(target_pos.0, target_pos.1));
+            let arrived = match need {
+                NeedKind::Fatigue => distance < 0.5,
+                _ => distance < 1.5
+            };
+
+            if arrived {
+                let name = names.get(entity).map_or("Someone", |n| n.name.as_str());
+
+                match need {
+                    NeedKind::Hunger => {
+                        if let (Some(hunger), Some(edible)) = (hungers.get_mut(entity), edibles.get(target)) {
+                            hunger.current = i32::min(hunger.max, hunger.current + edible.nutrition);
+                            messages::post(format!("{} eats", name));
+                        }
+                        entities.delete(target).expect("Unable to delete");
+                    }
+                    NeedKind::Thirst => {
+                        if let (Some(thirst), Some(drinkable)) = (thirsts.get_mut(entity), drinkables.get(target)) {
+                            thirst.current = i32::min(thirst.max, thirst.current + drinkable.hydration);
+                            messages::post(format!("{} drinks", name));
+                        }
+                        entities.delete(target).expect("Unable to delete");
+                    }
+                    NeedKind::Fatigue => {
+                        messages::post(format!("{} goes to sleep", name));
+                        asleep.insert(entity, components::Asleep{}).expect("Unable to insert sleep");
+                    }
+                }
+
+                finished.push(entity);
+                continue;
+            }
+
+            let faction = factions.get(entity).map(|f| f.kind);
+
+            match map.step_onto_as((pos.x, pos.y), target_pos, faction) {
+                Some((x, y)) => {
+                    if !map.can_enter(map.xy_idx(x, y)) || !doors::try_pass(&mut *map, &mut doors, &mut noises, x, y, faction) || !fluids::wade(&map, &mut wading, entity, map.xy_idx(x, y)) {
+                        continue;
+                    }
+
+                    let old_idx = map.xy_idx(pos.x, pos.y);
+                    let new_idx = map.xy_idx(x, y);
+                    map.blocked[old_idx] = false;
+                    map.blocked[new_idx] = true;
+
+                    pos.x = x;
+                    pos.y = y;
+                    if let Some(viewshed) = viewsheds.get_mut(entity) {
+                        viewshed.dirty = true;
+                    }
+                }
+                None => {
+                    // Can't get there, try something else next turn
+                    finished.push(entity);
+                }
+            }
+        }
+
+        for entity in finished {
+            seeking.remove(entity);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use specs::prelude::*;
+use rltk::BaseMap;
+
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::components;
+use crate::map;
+
+pub static WALL_ATTENUATION: i32 = 5;
+pub static SHOUT_LOUDNESS: i32 = 12;
+pub static COMBAT_LOUDNESS: i32 = 8;
+pub static DIG_LOUDNESS: i32 = 6;
+pub static DOOR_LOUDNESS: i32 = 4;
+
+pub struct Noise {
+    pub x: i32,
+    pub y: i32,
+    pub loudness: i32
+}
+
+/// Noises emitted this frame, waiting to be heard by the `HearingSystem`.
+#[derive(Default)]
+pub struct NoiseQueue {
+    pub noises: Vec<Noise>
+}
+
+impl NoiseQueue {
+    pub fn emit(&mut self, x: i32, y: i32, loudness: i32) {
+        self.noises.push(Noise { x, y, loudness });
+    }
+}
+
+/// Spreads a noise out from its source. It loses one point of loudness for every open tile it
+/// crosses and `WALL_ATTENUATION` for every opaque tile it has to pass through.
+/// Returns the loudness left at every tile the noise reaches.
+pub fn propagate(map: &map::Map, noise: &Noise) -> HashMap<usize, i32> {
+    let mut heard: HashMap<usize, i32> = HashMap::new();
+    let mut frontier: BinaryHeap<(i32, usize)> = BinaryHeap::new();
+
+    if !map.in_bounds(noise.x, noise.y) {
+        return heard;
+    }
+
+    let start = map.xy_idx(noise.x, noise.y);
+    heard.insert(start, noise.loudness);
+    frontier.push((noise.loudness, start));
+
+    while let Some((loudness, idx)) = frontier.pop() {
+        if heard.get(&idx).map_or(false, |best| *best > loudness) {
+            continue;
+        }
+
+        let x = idx as i32 % map.width;
+        let y = idx as i32 / map.width;
+
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+            let (nx, ny) = (x + dx, y + dy);
+            if !map.in_bounds(nx, ny) {
+                continue;
+            }
+
+            let next = map.xy_idx(nx, ny);
+            let cost = if map.is_opaque(next) { WALL_ATTENUATION } else { 1 };
+            let remaining = loudness - cost;
+
+            if remaining > 0 && heard.get(&next).map_or(true, |best| remaining > *best) {
+                heard.insert(next, remaining);
+                frontier.push((remaining, next));
+            }
+        }
+    }
+
+    heard
+}
+
+pub struct HearingSystem {}
+
+impl<'a> System<'a> for HearingSystem {
+    type SystemData = ( ReadExpect<'a, map::Map>,
+                        WriteExpect<'a, NoiseQueue>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::Hearing>,
+                        ReadStorage<'a, components::Position>,
+                        ReadStorage<'a, components::Viewshed>,
+                        WriteStorage<'a, components::Investigating>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (map, mut queue, entities, hearing, position, viewshed, mut investigating) = data;
+
+        let noises: Vec<Noise> = queue.noises.drain(..).collect();
+
+        for noise in noises.iter() {
+            let heard = propagate(&map, noise);
+
+            for (entity, hearing, pos, viewshed) in (&entities, &hearing, &position, &viewshed).join() {
+                let loudness = match heard.get(&map.xy_idx(pos.x, pos.y)) {
+                    Some(loudness) if *loudness >= hearing.threshold => *loudness,
+                    _ => continue
+                };
+
+                // If we can see where it came from, sight already tells us what is going on
+                if viewshed.visible_tiles.iter().any(|t| t.x == noise.x && t.y == noise.y) {
+                    continue;
+                }
+
+                if investigating.get(entity).map_or(true, |current| loudness >= current.loudness) {
+                    investigating.insert(entity, components::Investigating{ x: noise.x, y: noise.y, loudness })
+                        .expect("Unable to insert investigation");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use specs::prelude::*;
+use rand::Rng;
+
+use crate::components;
+use crate::map;
+use crate::materials;
+use crate::messages;
+use crate::noise;
+use crate::skills;
+
+/// Chance out of 100 of hitting a target standing right next to the shooter.
+pub static BASE_HIT_CHANCE: i32 = 90;
+/// Every tile the projectile flies takes this much off the chance of hitting.
+pub static HIT_CHANCE_PER_TILE: i32 = 5;
+/// Every level of combat skill adds this much to the chance of hitting.
+pub static HIT_CHANCE_PER_LEVEL: i32 = 3;
+/// Chance out of 100 of a projectile hitting someone it passes on the way to its target.
+pub static IN_THE_WAY_CHANCE: i32 = 30;
+
+/// How a tile on the line of fire looks from where the shooter stands.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Segment { Clear, Occupied, Blocked }
+
+/// The tiles a projectile passes over on its way from the shooter to the target, not counting the shooter's own tile.
+/// It keeps going past the target until it runs out of range.
+pub fn flight_path(from: (i32, i32), to: (i32, i32), range: i32) -> Vec<(i32, i32)> {
+    if from == to {
+        return Vec::new();
+    }
+
+    // Repeat the line past the target so a miss carries on in the same direction
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let mut path = Vec::new();
+    let mut start = from;
+
+    while (path.len() as i32) < range {
+        let end = (start.0 + dx, start.1 + dy);
+        path.extend(map::line(start, end).into_iter().skip(1));
+        start = end;
+    }
+
+    path.truncate(range as usize);
+    path
+}
+
+/// Walls and closed doors stop projectiles. Everything from the first one on is blocked, and tiles with creatures
+/// standing on them might catch the shot.
+pub fn line_of_fire(map: &map::Map, path: &[(i32, i32)], creatures: &[(i32, i32)]) -> Vec<Segment> {
+    let mut blocked = false;
+
+    path.iter().map(|(x, y)| {
+        if !map.in_bounds(*x, *y) {
+            blocked = true;
+        } else {
+            let idx = map.xy_idx(*x, *y);
+            blocked = blocked || map.glyph_map[idx] == '#' || map.door_closed(idx);
+        }
+
+        if blocked {
+            Segment::Blocked
+        } else if creatures.contains(&(*x, *y)) {
+            Segment::Occupied
+        } else {
+            Segment::Clear
+        }
+    }).collect()
+}
+
+pub fn hit_chance(distance: i32, combat: i32) -> i32 {
+    i32::max(5, BASE_HIT_CHANCE - HIT_CHANCE_PER_TILE * distance + HIT_CHANCE_PER_LEVEL * combat)
+}
+
+/// The carried item that hits hardest from a distance, if any.
+pub fn ranged_weapon(owner: Entity, entities: &Entities, carried: &ReadStorage<components::CarriedBy>,
+                     ranged: &ReadStorage<components::Ranged>) -> Option<Entity> {
+    (entities, carried, ranged).join()
+        .filter(|(_, c, _)| c.owner == owner)
+        .max_by_key(|(_, _, r)| r.damage)
+        .map(|(item, _, _)| item)
+}
+
+/// Looses projectiles along their line of fire. They can miss their target and fly on, hit whoever is in the way,
+/// or hit a wall. Thrown weapons land where they stopped.
+pub struct RangedCombatSystem {}
+
+impl<'a> System<'a> for RangedCombatSystem {
+    type SystemData = ( ReadExpect<'a, map::Map>,
+                        WriteExpect<'a, noise::NoiseQueue>,
+                        Entities<'a>,
+                        Read<'a, LazyUpdate>,
+                        WriteStorage<'a, components::WantsToShoot>,
+                        ReadStorage<'a, components::Name>,
+                        ReadStorage<'a, components::CombatStats>,
+                        ReadStorage<'a, components::Position>,
+                        WriteStorage<'a, components::SufferDamage>,
+                        WriteStorage<'a, components::Skills>,
+                        ReadStorage<'a, components::CarriedBy>,
+                        ReadStorage<'a, components::Ranged>,
+                        ReadStorage<'a, components::Weapon>,
+                        ReadStorage<'a, components::Armour>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (map, mut noises, entities, lazy, mut wants_shoot, names, combat_stats, positions, mut inflict_damage, mut skills,
+             carried, ranged, weapons, armour) = data;
+
+        let mut rng = rand::thread_rng();
+        let shots: Vec<(Entity, (i32, i32))> = (&entities, &wants_shoot).join().map(|(entity, shot)| (entity, (shot.x, shot.y))).collect();
+
+        for (shooter, target) in shots {
+            let from = match positions.get(shooter) {
+                Some(pos) => (pos.x, pos.y),
+                None => continue
+            };
+            if combat_stats.get(shooter).map_or(true, |stats| stats.hp <= 0) {
+                continue;
+            }
+
+            let name = names.get(shooter).map_or("Someone", |n| n.name.as_str());
+            let weapon = match ranged_weapon(shooter, &entities, &carried, &ranged) {
+                Some(weapon) => weapon,
+                None => {
+                    messages::post(format!("{} has nothing to shoot with", name));
+                    continue;
+                }
+            };
+            let stats = ranged.get(weapon).unwrap();
+            let weapon_name = names.get(weapon).map_or("shot".to_string(), |n| n.name.to_lowercase());
+
+            noises.emit(from.0, from.1, noise::COMBAT_LOUDNESS);
+            let combat = skills.get(shooter).map_or(0, |s| s.level(skills::SkillKind::Combat));
+            skills::train(&mut skills, &names, shooter, skills::SkillKind::Combat, skills::EXPERIENCE_PER_ACTION);
+
+            let creatures: Vec<(Entity, (i32, i32))> = (&entities, &combat_stats, &positions).join()
+                .filter(|(entity, stats, _)| *entity != shooter && stats.hp > 0)
+                .map(|(entity, _, pos)| (entity, (pos.x, pos.y)))
+                .collect();
+
+            let path = flight_path(from, target, stats.range);
+            let segments = line_of_fire(&map, &path, &creatures.iter().map(|(_, tile)| *tile).collect::<Vec<_>>());
+            let mut landed_at = from;
+            let mut struck = None;
+
+            for (distance, (tile, segment)) in path.iter().zip(segments.iter()).enumerate() {
+                match segment {
+                    Segment::Blocked => {
+                        messages::post(format!("{}'s {} strikes the wall", name, weapon_name));
+                        break;
+                    }
+                    Segment::Occupied => {
+                        let victim = creatures.iter().find(|(_, at)| at == tile).map(|(entity, _)| *entity).unwrap();
+                        let victim_name = names.get(victim).map_or("something", |n| n.name.as_str());
+
+                        let chance = if *tile == target { hit_chance(distance as i32 + 1, combat) } else { IN_THE_WAY_CHANCE };
+                        if rng.gen_range(0, 100) < chance {
+                            struck = Some(victim);
+                            landed_at = *tile;
+                            break;
+                        } else if *tile == target {
+                            messages::post(format!("{}'s {} misses {}", name, weapon_name, victim_name));
+                        }
+                    }
+                    Segment::Clear => {}
+                }
+
+                landed_at = *tile;
+            }
+
+            if let Some(victim) = struck {
+                let victim_name = names.get(victim).map_or("something", |n| n.name.as_str());
+                let (_, protection) = materials::equipment(victim, &carried, &weapons, &armour);
+                let defense = combat_stats.get(victim).map_or(0, |s| s.defense);
+                let damage = i32::max(0, stats.damage + combat / 2 - defense - protection);
+
+                if damage == 0 {
+                    messages::post(format!("{}'s {} glances off {}", name, weapon_name, victim_name));
+                } else {
+                    messages::post(format!("{}'s {} hits {} for {} hp", name, weapon_name, victim_name, damage));
+                    components::SufferDamage::new_damage(&mut inflict_damage, victim, damage);
+                }
+            }
+
+            // Deferred, so the carry system doesn't pull it straight back to the thrower
+            if stats.thrown {
+                lazy.remove::<components::CarriedBy>(weapon);
+                lazy.insert(weapon, components::Position{ x: landed_at.0, y: landed_at.1 });
+            }
+        }
+
+        wants_shoot.clear();
+    }
+}
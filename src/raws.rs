@@ -0,0 +1,295 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+
+use crate::components;
+use crate::components::ItemCategory;
+use crate::heat;
+use crate::materials;
+use crate::materials::MaterialKind;
+use crate::skills::QualityTier;
+use crate::workshops::WorkshopKind;
+
+static MATERIALS_RAW: &str = include_str!("../static/materials.txt");
+static ITEMS_RAW: &str = include_str!("../static/items.txt");
+static RECIPES_RAW: &str = include_str!("../static/recipes.txt");
+
+#[derive(Clone)]
+pub struct Material {
+    pub name: String,
+    pub kind: MaterialKind,
+    /// Kilograms per cubic metre.
+    pub density: i32,
+    pub hardness: i32,
+    pub value: i32,
+    pub colour: Color,
+}
+
+#[derive(Clone)]
+pub struct ItemTemplate {
+    pub name: String,
+    pub glyph: char,
+    pub category: ItemCategory,
+    pub colour: Color,
+    pub nutrition: Option<i32>,
+    pub hydration: Option<i32>,
+    pub bed: bool,
+    /// Ignition temperature and turns of fuel, for things that burn.
+    pub flammable: Option<(i32, i32)>,
+    /// What the item is usually made of. Crafted items may be made of something else, see `materials::crafted_material`.
+    pub material: Option<Material>,
+    /// In litres.
+    pub volume: f32,
+    pub value: i32,
+    pub weapon: Option<i32>,
+    pub armour: Option<i32>,
+    /// Damage and range in tiles, for things that can be shot or thrown.
+    pub ranged: Option<(i32, i32)>,
+    pub thrown: bool,
+}
+
+pub struct Recipe {
+    pub name: String,
+    pub workshop: WorkshopKind,
+    pub inputs: Vec<(String, usize)>,
+    pub outputs: Vec<(String, usize)>,
+    pub turns: i32,
+}
+
+/// Materials, item templates and recipes, parsed from the data files in `static/`.
+pub struct Raws {
+    pub materials: Vec<Material>,
+    pub items: Vec<ItemTemplate>,
+    pub recipes: Vec<Recipe>,
+}
+
+/// Splits a data file into its non-empty, non-comment lines, each cut into trimmed `|` separated fields.
+pub fn data_lines(raw: &str) -> Vec<Vec<&str>> {
+    raw.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.split('|').map(|field| field.trim()).collect())
+        .collect()
+}
+
+fn parse_category(name: &str) -> ItemCategory {
+    match name {
+        "stone" => ItemCategory::Stone,
+        "ore" => ItemCategory::Ore,
+        "wood" => ItemCategory::Wood,
+        "food" => ItemCategory::Food,
+        "drink" => ItemCategory::Drink,
+        "furniture" => ItemCategory::Furniture,
+        "goods" => ItemCategory::Goods,
+        _ => panic!("Unknown item category '{}'", name)
+    }
+}
+
+fn parse_colour(rgb: &str) -> Color {
+    let channels: Vec<u8> = rgb.split(',')
+        .map(|channel| channel.trim().parse().expect("Colour channels must be numbers from 0 to 255"))
+        .collect();
+
+    if channels.len() != 3 {
+        panic!("Colours must be given as r,g,b, got '{}'", rgb);
+    }
+
+    Color::from_rgba(channels[0], channels[1], channels[2], 1.0)
+}
+
+/// Parses "2 Plank, Log" into [("Plank", 2), ("Log", 1)].
+fn parse_item_list(list: &str) -> Vec<(String, usize)> {
+    list.split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut words = entry.splitn(2, ' ');
+            let first = words.next().unwrap_or("");
+
+            match (first.parse::<usize>(), words.next()) {
+                (Ok(count), Some(name)) => (name.trim().to_string(), count),
+                _ => (entry.to_string(), 1)
+            }
+        })
+        .collect()
+}
+
+impl Raws {
+    pub fn load() -> Raws {
+        let mut raws = Raws { materials: Vec::new(), items: Vec::new(), recipes: Vec::new() };
+
+        for fields in data_lines(MATERIALS_RAW) {
+            if fields.len() != 6 {
+                panic!("Materials need a name, kind, density, hardness, value and colour: {:?}", fields);
+            }
+
+            raws.materials.push(Material {
+                name: fields[0].to_string(),
+                kind: MaterialKind::from_name(fields[1]).expect("Unknown material kind"),
+                density: fields[2].parse().expect("Material density must be a number"),
+                hardness: fields[3].parse().expect("Material hardness must be a number"),
+                value: fields[4].parse().expect("Material value must be a number"),
+                colour: parse_colour(fields[5]),
+            });
+        }
+
+        for fields in data_lines(ITEMS_RAW) {
+            if fields.len() < 4 {
+                panic!("Item templates need at least a name, glyph, category and colour: {:?}", fields);
+            }
+
+            let mut template = ItemTemplate {
+                name: fields[0].to_string(),
+                glyph: fields[1].chars().next().expect("Item templates need a glyph"),
+                category: parse_category(fields[2]),
+                colour: parse_colour(fields[3]),
+                nutrition: None,
+                hydration: None,
+                bed: false,
+                flammable: None,
+                material: None,
+                volume: 1.0,
+                value: 1,
+                weapon: None,
+                armour: None,
+                ranged: None,
+                thrown: false,
+            };
+
+            if let Some(properties) = fields.get(4) {
+                for property in properties.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+                    let words: Vec<&str> = property.split_whitespace().collect();
+
+                    match words.as_slice() {
+                        ["edible", amount] => template.nutrition = amount.parse().ok(),
+                        ["drinkable", amount] => template.hydration = amount.parse().ok(),
+                        ["bed"] => template.bed = true,
+                        ["flammable", ignition, fuel] => {
+                            template.flammable = match (ignition.parse(), fuel.parse()) {
+                                (Ok(ignition), Ok(fuel)) => Some((ignition, fuel)),
+                                _ => panic!("Flammable items need an ignition temperature and turns of fuel: {}", template.name)
+                            };
+                        }
+                        ["material", name] => {
+                            let material = raws.material(name).unwrap_or_else(|| panic!("{} is made of unknown material '{}'", template.name, name));
+                            template.material = Some(material.clone());
+                        }
+                        ["volume", litres] => template.volume = litres.parse().expect("Item volume must be a number"),
+                        ["value", coins] => template.value = coins.parse().expect("Item value must be a number"),
+                        ["weapon", damage] => template.weapon = Some(damage.parse().expect("Weapon damage must be a number")),
+                        ["armour", defense] => template.armour = Some(defense.parse().expect("Armour defense must be a number")),
+                        ["ranged", damage, range] => {
+                            template.ranged = match (damage.parse(), range.parse()) {
+                                (Ok(damage), Ok(range)) => Some((damage, range)),
+                                _ => panic!("Ranged items need a damage and a range: {}", template.name)
+                            };
+                        }
+                        ["thrown"] => template.thrown = true,
+                        _ => panic!("Unknown item property '{}' on {}", property, template.name)
+                    }
+                }
+            }
+
+            raws.items.push(template);
+        }
+
+        for fields in data_lines(RECIPES_RAW) {
+            if fields.len() != 5 {
+                panic!("Recipes need a name, workshop, inputs, outputs and turns: {:?}", fields);
+            }
+
+            let recipe = Recipe {
+                name: fields[0].to_string(),
+                workshop: WorkshopKind::from_name(fields[1]).expect("Unknown workshop in recipe"),
+                inputs: parse_item_list(fields[2]),
+                outputs: parse_item_list(fields[3]),
+                turns: fields[4].parse().expect("Recipe turns must be a number"),
+            };
+
+            for (name, _count) in recipe.inputs.iter().chain(recipe.outputs.iter()) {
+                if raws.item(name).is_none() {
+                    panic!("Recipe '{}' uses unknown item '{}'", recipe.name, name);
+                }
+            }
+
+            raws.recipes.push(recipe);
+        }
+
+        raws
+    }
+
+    pub fn material(&self, name: &str) -> Option<&Material> {
+        self.materials.iter().find(|material| material.name == name)
+    }
+
+    pub fn item(&self, name: &str) -> Option<&ItemTemplate> {
+        self.items.iter().find(|template| template.name == name)
+    }
+
+    /// Indices into `recipes` of everything the given workshop can make.
+    pub fn recipes_for(&self, kind: WorkshopKind) -> Vec<usize> {
+        self.recipes.iter()
+            .enumerate()
+            .filter(|(_, recipe)| recipe.workshop == kind)
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Builds an item from its template, made of its usual material. Works with both `World::create_entity` and `LazyUpdate::create_entity`.
+pub fn build_item<B: Builder>(builder: B, template: &ItemTemplate, x: i32, y: i32) -> Entity {
+    build(builder, template, template.material.as_ref(), None, x, y)
+}
+
+/// Builds an item from its template, made of something other than its usual material.
+pub fn build_item_of<B: Builder>(builder: B, template: &ItemTemplate, material: Option<&Material>, x: i32, y: i32) -> Entity {
+    build(builder, template, material, None, x, y)
+}
+
+/// Builds an item somebody made, which carries the material it was made from and how well it was made.
+pub fn build_crafted_item<B: Builder>(builder: B, template: &ItemTemplate, material: Option<&Material>, quality: QualityTier, x: i32, y: i32) -> Entity {
+    build(builder, template, material, Some(quality), x, y)
+}
+
+fn build<B: Builder>(builder: B, template: &ItemTemplate, material: Option<&Material>, quality: Option<QualityTier>, x: i32, y: i32) -> Entity {
+    let tier = quality.unwrap_or(QualityTier::Standard);
+
+    let mut builder = builder
+        .with(components::Position{ x, y })
+        .with(components::Renderable{ glyph: template.glyph, color: material.map_or(template.colour, |m| m.colour) })
+        .with(components::Name{ name: template.name.clone() })
+        .with(components::Item{ category: template.category })
+        .with(components::Temperature{ degrees: heat::AMBIENT_TEMPERATURE })
+        .with(components::Weight{ kilograms: materials::weight(template, material) })
+        .with(components::Value{ coins: materials::value(template, material, tier) });
+
+    if let Some(material) = material {
+        builder = builder.with(components::MadeOf{ material: material.name.clone() });
+    }
+    if let Some(tier) = quality {
+        builder = builder.with(components::Quality{ tier });
+    }
+    if let Some(damage) = template.weapon {
+        builder = builder.with(components::Weapon{ damage: materials::rating(damage, material, tier) });
+    }
+    if let Some(defense) = template.armour {
+        builder = builder.with(components::Armour{ defense: materials::rating(defense, material, tier) });
+    }
+    if let Some((damage, range)) = template.ranged {
+        builder = builder.with(components::Ranged{ damage: materials::rating(damage, material, tier), range, thrown: template.thrown });
+    }
+
+    if let Some(nutrition) = template.nutrition {
+        builder = builder.with(components::Edible{ nutrition });
+    }
+    if let Some(hydration) = template.hydration {
+        builder = builder.with(components::Drinkable{ hydration });
+    }
+    if template.bed {
+        builder = builder.with(components::Bed{});
+    }
+    if let Some((ignition, fuel)) = template.flammable {
+        builder = builder.with(components::Flammable{ ignition, fuel });
+    }
+
+    builder.build()
+}
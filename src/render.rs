@@ -0,0 +1,73 @@
+use quicksilver::prelude::*;
+
+use crate::tileset;
+
+/// Everything on the screen is drawn through this, so the same map view and panels can go to a window or a terminal.
+/// Positions and sizes are in pixels of the screen layout. Renderers that draw in character cells work out
+/// which cells that covers themselves.
+pub trait Renderer {
+    /// Fills an area with a colour. Translucent colours shade whatever has been drawn there already.
+    fn fill(&mut self, area: Rectangle, colour: Color) -> Result<()>;
+
+    fn line(&mut self, from: Vector, to: Vector, thickness: f32, colour: Color) -> Result<()>;
+
+    /// Draws something on the map: its sprite if the tileset has one for its name, otherwise its glyph in its colour.
+    fn tile(&mut self, area: Rectangle, glyph: char, name: Option<&str>, colour: Color) -> Result<()>;
+
+    fn text(&mut self, text: &str, position: Vector, font_size: f32, colour: Color) -> Result<()>;
+}
+
+/// Draws to the quicksilver window, with map tiles from the current tileset.
+pub struct WindowRenderer<'a> {
+    pub window: &'a mut Window,
+    pub tileset: &'a mut Asset<tileset::Tileset>,
+}
+
+impl<'a> Renderer for WindowRenderer<'a> {
+    fn fill(&mut self, area: Rectangle, colour: Color) -> Result<()> {
+        self.window.draw(&area, Col(colour));
+        Ok(())
+    }
+
+    fn line(&mut self, from: Vector, to: Vector, thickness: f32, colour: Color) -> Result<()> {
+        self.window.draw_ex(&Line::new(from, to).with_thickness(thickness), Col(colour), Transform::IDENTITY, 5);
+        Ok(())
+    }
+
+    fn tile(&mut self, area: Rectangle, glyph: char, name: Option<&str>, colour: Color) -> Result<()> {
+        let window = &mut self.window;
+
+        self.tileset.execute(|tileset| {
+            if let Some(image) = tileset.get(glyph, name) {
+                // Sprites keep their own colours unless they are being shaded, like remembered tiles are
+                if tileset.tinted || colour.a < 1.0 {
+                    window.draw(&area, Blended(&image, colour));
+                } else {
+                    window.draw(&area, Img(&image));
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn text(&mut self, text: &str, position: Vector, font_size: f32, colour: Color) -> Result<()> {
+        let window = &mut self.window;
+        let text = text.to_string();
+        let mut to_draw = Asset::new(Font::load("Cascadia.ttf").and_then(move |font| {
+            font.render(
+                &text,
+                &FontStyle::new(font_size, colour),
+            )
+        }));
+
+        to_draw.execute(|image| {
+            window.draw(
+                &image
+                    .area()
+                    .translate((position.x, position.y)),
+                Img(&image),
+            );
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,31 @@
+pub static FRAMES_PER_TURN: u64 = 10;
+
+/// Systems run every frame, but creatures should only act once per game turn.
+/// Turn-based systems check `is_new_turn` and return early otherwise.
+pub struct Scheduler {
+    pub frame: u64,
+    pub turn: u64,
+    pub frames_per_turn: u64,
+}
+
+impl Scheduler {
+    pub fn new(frames_per_turn: u64) -> Scheduler {
+        Scheduler {
+            frame: 0,
+            turn: 0,
+            frames_per_turn,
+        }
+    }
+
+    pub fn advance(&mut self) {
+        self.frame += 1;
+
+        if self.is_new_turn() {
+            self.turn += 1;
+        }
+    }
+
+    pub fn is_new_turn(&self) -> bool {
+        self.frame % self.frames_per_turn == 0
+    }
+}
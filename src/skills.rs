@@ -0,0 +1,135 @@
+use specs::prelude::*;
+use rand::Rng;
+
+use crate::components;
+use crate::jobs;
+use crate::messages;
+use crate::raws;
+use crate::workshops::WorkshopKind;
+
+pub static MAX_SKILL_LEVEL: i32 = 10;
+/// Level n needs n² times this much experience.
+pub static EXPERIENCE_PER_LEVEL: i32 = 20;
+/// How many tiles further a dwarf will walk for a job that suits them, per skill level.
+pub static SKILL_PREFERENCE: f32 = 1.5;
+pub static STARTING_EXPERIENCE: i32 = 80;
+/// Experience for each turn spent working, each delivered haul and each blow struck.
+pub static EXPERIENCE_PER_ACTION: i32 = 1;
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum SkillKind { Mining, Masonry, Smithing, Combat, Hauling }
+
+pub static SKILL_KINDS: [SkillKind; 5] = [SkillKind::Mining, SkillKind::Masonry, SkillKind::Smithing, SkillKind::Combat, SkillKind::Hauling];
+
+static LEVEL_NAMES: [&str; 11] = ["Dabbling", "Novice", "Adequate", "Competent", "Skilled", "Proficient",
+                                  "Talented", "Adept", "Expert", "Professional", "Legendary"];
+
+impl SkillKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SkillKind::Mining => "Mining",
+            SkillKind::Masonry => "Masonry",
+            SkillKind::Smithing => "Smithing",
+            SkillKind::Combat => "Combat",
+            SkillKind::Hauling => "Hauling",
+        }
+    }
+
+    /// What someone with the skill is called, e.g. "miner".
+    pub fn practitioner(&self) -> &'static str {
+        match self {
+            SkillKind::Mining => "miner",
+            SkillKind::Masonry => "mason",
+            SkillKind::Smithing => "smith",
+            SkillKind::Combat => "fighter",
+            SkillKind::Hauling => "hauler",
+        }
+    }
+}
+
+pub fn level_for(experience: i32) -> i32 {
+    i32::min(MAX_SKILL_LEVEL, (experience as f32 / EXPERIENCE_PER_LEVEL as f32).sqrt() as i32)
+}
+
+pub fn level_name(level: i32) -> &'static str {
+    LEVEL_NAMES[level.max(0).min(MAX_SKILL_LEVEL) as usize]
+}
+
+/// Every new dwarf has some experience in one skill.
+pub fn random_starting_skill() -> SkillKind {
+    let mut rng = rand::thread_rng();
+    SKILL_KINDS[rng.gen_range(0, SKILL_KINDS.len())]
+}
+
+/// Turns of work put into a job each turn.
+pub fn work_rate(level: i32) -> i32 {
+    1 + level / 4
+}
+
+/// The skill a job trains and relies on, if any.
+pub fn job_skill(job: &jobs::Job, raws: &raws::Raws) -> Option<SkillKind> {
+    match job.kind {
+        jobs::JobKind::Dig => Some(SkillKind::Mining),
+        jobs::JobKind::Haul => Some(SkillKind::Hauling),
+        jobs::JobKind::Build | jobs::JobKind::Deconstruct => Some(SkillKind::Masonry),
+        jobs::JobKind::Craft => {
+            match job.recipe.map(|recipe| raws.recipes[recipe].workshop) {
+                Some(WorkshopKind::Mason) => Some(SkillKind::Masonry),
+                Some(WorkshopKind::Smith) => Some(SkillKind::Smithing),
+                _ => None
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Copy, Clone, Debug)]
+pub enum QualityTier { Standard, WellCrafted, Fine, Superior, Exceptional, Masterwork }
+
+static QUALITY_TIERS: [QualityTier; 6] = [QualityTier::Standard, QualityTier::WellCrafted, QualityTier::Fine,
+                                          QualityTier::Superior, QualityTier::Exceptional, QualityTier::Masterwork];
+
+impl QualityTier {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QualityTier::Standard => "standard",
+            QualityTier::WellCrafted => "well-crafted",
+            QualityTier::Fine => "fine",
+            QualityTier::Superior => "superior",
+            QualityTier::Exceptional => "exceptional",
+            QualityTier::Masterwork => "masterwork",
+        }
+    }
+
+    fn index(&self) -> i32 {
+        QUALITY_TIERS.iter().position(|tier| tier == self).unwrap_or(0) as i32
+    }
+
+    /// Each tier above standard adds a quarter to what something is worth.
+    pub fn value_multiplier(&self) -> f32 {
+        1.0 + 0.25 * self.index() as f32
+    }
+
+    /// Added to the damage of weapons and the defense of armour.
+    pub fn bonus(&self) -> i32 {
+        self.index() / 2
+    }
+}
+
+/// Rolls the quality of something made by a crafter of the given level. Every two levels raise the best
+/// quality within reach by one tier.
+pub fn roll_quality(level: i32) -> QualityTier {
+    let mut rng = rand::thread_rng();
+    let best = i32::min(QUALITY_TIERS.len() as i32 - 1, level / 2);
+    let tier = rng.gen_range(best / 2, best + 1);
+
+    QUALITY_TIERS[tier as usize]
+}
+
+/// Gives someone experience in a skill, and announces it when they get better at it.
+pub fn train(skills: &mut WriteStorage<components::Skills>, names: &ReadStorage<components::Name>, entity: Entity, kind: SkillKind, amount: i32) {
+    if let Some(level) = skills.get_mut(entity).and_then(|s| s.gain(kind, amount)) {
+        if let Some(name) = names.get(entity) {
+            messages::post(format!("{} has become a better {} ({})", name.name, kind.practitioner(), level_name(level)));
+        }
+    }
+}
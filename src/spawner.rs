@@ -0,0 +1,91 @@
+use quicksilver::prelude::*;
+use specs::prelude::*;
+
+use crate::components;
+use crate::factions;
+use crate::skills;
+
+// Like `raws::build_item`, these work with both `World::create_entity` and `LazyUpdate::create_entity`.
+
+pub fn dwarf<B: Builder>(builder: B, name: &str, glyph: char, x: i32, y: i32) -> Entity {
+    let mut skills = components::Skills::default();
+    skills.gain(skills::random_starting_skill(), skills::STARTING_EXPERIENCE);
+
+    builder
+    .with(components::Position { x, y })
+    .with(components::Renderable {
+        glyph,
+        color: Color::BLACK,
+    })
+    .with(components::Player{})
+    .with(components::Dwarf{})
+    .with(components::Viewshed{ visible_tiles : Vec::new(), range : 8, dirty: true })
+    .with(components::Name{ name: name.to_string() })
+    .with(components::BlocksTile{})
+    .with(components::Faction{ kind: factions::FactionKind::Dwarves })
+    .with(components::CombatStats{ max_hp: 30, hp: 30, defense: 2, power: 5 })
+    .with(components::Hunger{ current: 3000, max: 3000 })
+    .with(components::Thirst{ current: 2000, max: 2000 })
+    .with(components::Fatigue{ current: 4000, max: 4000 })
+    .with(skills)
+    .build()
+}
+
+pub fn goblin<B: Builder>(builder: B, x: i32, y: i32) -> Entity {
+    builder
+    .with(components::Position { x, y })
+    .with(components::Renderable {
+        glyph: 'g',
+        color: Color::GREEN,
+    })
+    .with(components::Monster{})
+    .with(components::Viewshed{ visible_tiles : Vec::new(), range : 8, dirty: true })
+    .with(components::Name{ name: "Protogoblin".to_string() })
+    .with(components::BlocksTile{})
+    .with(components::Faction{ kind: factions::FactionKind::Goblins })
+    .with(components::CombatStats{ max_hp: 16, hp: 16, defense: 1, power: 4 })
+    .with(components::Hearing{ threshold: 2 })
+    .build()
+}
+
+pub fn hare<B: Builder>(builder: B, x: i32, y: i32) -> Entity {
+    builder
+    .with(components::Position { x, y })
+    .with(components::Renderable {
+        glyph: 'h',
+        color: Color::from_rgba(139, 90, 43, 1.0),
+    })
+    .with(components::Monster{})
+    .with(components::Viewshed{ visible_tiles : Vec::new(), range : 6, dirty: true })
+    .with(components::Name{ name: "Cave hare".to_string() })
+    .with(components::BlocksTile{})
+    .with(components::Faction{ kind: factions::FactionKind::Wildlife })
+    .with(components::CombatStats{ max_hp: 6, hp: 6, defense: 0, power: 1 })
+    .build()
+}
+
+pub fn merchant<B: Builder>(builder: B, x: i32, y: i32) -> Entity {
+    builder
+    .with(components::Position { x, y })
+    .with(components::Renderable {
+        glyph: 'm',
+        color: Color::BLUE,
+    })
+    .with(components::Monster{})
+    .with(components::Viewshed{ visible_tiles : Vec::new(), range : 8, dirty: true })
+    .with(components::Name{ name: "Travelling merchant".to_string() })
+    .with(components::BlocksTile{})
+    .with(components::Faction{ kind: factions::FactionKind::Merchants })
+    .with(components::CombatStats{ max_hp: 20, hp: 20, defense: 1, power: 3 })
+    .build()
+}
+
+/// Spawns a creature by the name used in spawn tables.
+pub fn creature<B: Builder>(builder: B, name: &str, x: i32, y: i32) -> Entity {
+    match name {
+        "Protogoblin" => goblin(builder, x, y),
+        "Cave hare" => hare(builder, x, y),
+        "Travelling merchant" => merchant(builder, x, y),
+        _ => panic!("Unknown creature '{}'", name)
+    }
+}
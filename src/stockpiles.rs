@@ -0,0 +1,156 @@
+use specs::prelude::*;
+
+use std::collections::HashMap;
+
+use crate::components;
+use crate::components::ItemCategory;
+use crate::jobs;
+use crate::map;
+use crate::scheduler;
+
+pub static DEFAULT_TILE_CAPACITY: usize = 1;
+/// Turns between looks around for loose items that need hauling.
+pub static HAULING_INTERVAL: u64 = 5;
+
+pub static FILTER_PRESETS: [(&str, &[ItemCategory]); 6] = [
+    ("Everything", &[ItemCategory::Stone, ItemCategory::Ore, ItemCategory::Wood, ItemCategory::Food, ItemCategory::Drink, ItemCategory::Furniture, ItemCategory::Goods]),
+    ("Stone and ore", &[ItemCategory::Stone, ItemCategory::Ore]),
+    ("Food and drink", &[ItemCategory::Food, ItemCategory::Drink]),
+    ("Wood", &[ItemCategory::Wood]),
+    ("Furniture", &[ItemCategory::Furniture]),
+    ("Finished goods", &[ItemCategory::Goods]),
+];
+
+pub struct StockpileZone {
+    pub x1: i32,
+    pub y1: i32,
+    pub x2: i32,
+    pub y2: i32,
+    pub preset: usize,
+    pub capacity_per_tile: usize,
+}
+
+impl StockpileZone {
+    pub fn new(corner: (i32, i32), other_corner: (i32, i32)) -> StockpileZone {
+        StockpileZone {
+            x1: i32::min(corner.0, other_corner.0),
+            y1: i32::min(corner.1, other_corner.1),
+            x2: i32::max(corner.0, other_corner.0),
+            y2: i32::max(corner.1, other_corner.1),
+            preset: 0,
+            capacity_per_tile: DEFAULT_TILE_CAPACITY,
+        }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x1 && x <= self.x2 && y >= self.y1 && y <= self.y2
+    }
+
+    pub fn accepts(&self, category: ItemCategory) -> bool {
+        FILTER_PRESETS[self.preset].1.contains(&category)
+    }
+
+    pub fn filter_name(&self) -> &'static str {
+        FILTER_PRESETS[self.preset].0
+    }
+
+    pub fn cycle_filter(&mut self) {
+        self.preset = (self.preset + 1) % FILTER_PRESETS.len();
+    }
+
+    /// Zones are designated as rectangles, but only the floor tiles inside them can hold items.
+    pub fn floor_tiles(&self, map: &map::Map) -> Vec<(i32, i32)> {
+        let mut tiles = Vec::new();
+
+        for y in self.y1..=self.y2 {
+            for x in self.x1..=self.x2 {
+                if map.in_bounds(x, y) && map.glyph_map[map.xy_idx(x, y)] != '#' {
+                    tiles.push((x, y));
+                }
+            }
+        }
+
+        tiles
+    }
+}
+
+#[derive(Default)]
+pub struct Stockpiles {
+    pub zones: Vec<StockpileZone>,
+    /// First corner of a zone that is being designated.
+    pub pending_corner: Option<(i32, i32)>,
+}
+
+impl Stockpiles {
+    pub fn accepts_at(&self, x: i32, y: i32, category: ItemCategory) -> bool {
+        self.zones.iter().any(|zone| zone.contains(x, y) && zone.accepts(category))
+    }
+}
+
+/// Posts haul jobs for loose items that are not already sitting in a stockpile that wants them.
+pub struct HaulingSystem {}
+
+impl<'a> System<'a> for HaulingSystem {
+    type SystemData = ( ReadExpect<'a, map::Map>,
+                        ReadExpect<'a, Stockpiles>,
+                        WriteExpect<'a, jobs::JobBoard>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::Item>,
+                        ReadStorage<'a, components::Position>,
+                        ReadStorage<'a, components::CarriedBy>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (map, stockpiles, mut board, scheduler, entities, items, positions, carried) = data;
+
+        if !scheduler.is_new_turn() || scheduler.turn % HAULING_INTERVAL != 0 || stockpiles.zones.is_empty() {
+            return;
+        }
+
+        // Count what is lying on each tile plus everything already on its way there
+        let mut occupancy: HashMap<(i32, i32), usize> = HashMap::new();
+        for (_item, pos, _loose) in (&items, &positions, !&carried).join() {
+            *occupancy.entry((pos.x, pos.y)).or_insert(0) += 1;
+        }
+
+        // Leave alone anything already being hauled or set aside for a job
+        let being_hauled = board.reserved_items();
+
+        for job in board.jobs.iter().filter(|job| job.kind == jobs::JobKind::Haul) {
+            let drop_off = job.destination.unwrap_or((job.x, job.y));
+            *occupancy.entry(drop_off).or_insert(0) += 1;
+        }
+
+        let mut new_jobs: Vec<(Entity, (i32, i32), (i32, i32))> = Vec::new();
+
+        for (entity, item, pos, _loose) in (&entities, &items, &positions, !&carried).join() {
+            if being_hauled.contains(&entity) || stockpiles.accepts_at(pos.x, pos.y, item.category) {
+                continue;
+            }
+
+            let mut nearest: Option<(f32, (i32, i32))> = None;
+
+            for zone in stockpiles.zones.iter().filter(|zone| zone.accepts(item.category)) {
+                for tile in zone.floor_tiles(&map) {
+                    if *occupancy.get(&tile).unwrap_or(&0) >= zone.capacity_per_tile {
+                        continue;
+                    }
+
+                    let distance = rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(pos.x, pos.y), rltk::Point::new(tile.0, tile.1));
+                    if nearest.map_or(true, |(best, _)| distance < best) {
+                        nearest = Some((distance, tile));
+                    }
+                }
+            }
+
+            if let Some((_distance, tile)) = nearest {
+                *occupancy.entry(tile).or_insert(0) += 1;
+                new_jobs.push((entity, (pos.x, pos.y), tile));
+            }
+        }
+
+        for (item, from, to) in new_jobs {
+            board.post_haul(item, from, to);
+        }
+    }
+}
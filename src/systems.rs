@@ -3,7 +3,16 @@ use quicksilver::prelude::*;
 use rand::Rng;
 
 use crate::components;
+use crate::doors;
 use crate::map;
+use crate::factions;
+use crate::fluids;
+use crate::heat;
+use crate::materials;
+use crate::messages;
+use crate::scheduler;
+use crate::skills;
+use crate::noise;
 use rltk::{Algorithm2D};
 
 impl<'a> System<'a> for components::RandomMover {
@@ -24,39 +33,423 @@ impl<'a> System<'a> for components::RandomMover {
 
 pub struct MonsterAI {}
 
+#[derive(Copy, Clone)]
+struct Sighting {
+    entity: Entity,
+    x: i32,
+    y: i32,
+    distance: f32
+}
+
+fn closest(current: Option<Sighting>, candidate: Sighting) -> Option<Sighting> {
+    match current {
+        Some(c) if c.distance <= candidate.distance => Some(c),
+        _ => Some(candidate)
+    }
+}
+
 impl<'a> System<'a> for MonsterAI {
     type SystemData = ( WriteExpect<'a, map::Map>,
-                        ReadExpect<'a, components::PlayerPosition>,
+                        ReadExpect<'a, factions::FactionTable>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        WriteExpect<'a, noise::NoiseQueue>,
+                        Entities<'a>,
                         WriteStorage<'a, components::Viewshed>,
                         ReadStorage<'a, components::Monster>,
                         ReadStorage<'a, components::Name>,
-                        WriteStorage<'a, components::Position>);
+                        ReadStorage<'a, components::Faction>,
+                        ReadStorage<'a, components::Player>,
+                        WriteStorage<'a, components::Position>,
+                        WriteStorage<'a, components::WantsToMelee>,
+                        WriteStorage<'a, components::Investigating>,
+                        WriteStorage<'a, components::Door>,
+                        WriteStorage<'a, components::Wading>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut map, faction_table, scheduler, mut noises, entities, mut viewshed, monster, name, faction, player, mut position,
+             mut wants_melee, mut investigating, mut doors, mut wading) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        // Snapshot where everyone is, since monsters move while we iterate
+        let creatures: Vec<(Entity, i32, i32, factions::FactionKind)> = (&entities, &position, &faction).join()
+            .map(|(entity, pos, faction)| (entity, pos.x, pos.y, faction.kind))
+            .collect();
+
+        for (entity, viewshed, _monster, name, faction, pos) in (&entities, &mut viewshed, &monster, &name, &faction, &mut position).join() {
+            let here = rltk::Point::new(pos.x, pos.y);
+
+            let mut prey: Option<Sighting> = None;
+            let mut threat: Option<Sighting> = None;
+            let mut trader: Option<Sighting> = None;
+
+            for (other, x, y, other_faction) in creatures.iter() {
+                if *other == entity || !viewshed.visible_tiles.iter().any(|t| t.x == *x && t.y == *y) {
+                    continue;
+                }
+
+                let sighting = Sighting {
+                    entity: *other,
+                    x: *x,
+                    y: *y,
+                    distance: rltk::DistanceAlg::Pythagoras.distance2d(here, rltk::Point::new(*x, *y))
+                };
+
+                match faction_table.reaction(faction.kind, *other_faction) {
+                    factions::Reaction::Attack => prey = closest(prey, sighting),
+                    factions::Reaction::Flee => threat = closest(threat, sighting),
+                    factions::Reaction::Trade => trader = closest(trader, sighting),
+                    factions::Reaction::Ignore => {}
+                }
+            }
+
+            let mut destination: Option<(i32, i32)> = None;
+
+            if let Some(threat) = threat {
+                // Step to whichever neighbouring tile puts the most distance between us and the threat
+                let mut best_distance = threat.distance;
+
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                    let (x, y) = (pos.x + dx, pos.y + dy);
+                    if !map.in_bounds(x, y) || map.blocked[map.xy_idx(x, y)] || map.locked_out(map.xy_idx(x, y), Some(faction.kind)) {
+                        continue;
+                    }
+
+                    let distance = rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(x, y), rltk::Point::new(threat.x, threat.y));
+                    if distance > best_distance {
+                        best_distance = distance;
+                        destination = Some((x, y));
+                    }
+                }
+            } else if let Some(prey) = prey {
+                investigating.remove(entity);
+
+                if prey.distance < 1.5 {
+                    if player.get(prey.entity).is_some() {
+                        messages::post(format!("{} shouts insults", name.name));
+                        noises.emit(pos.x, pos.y, noise::SHOUT_LOUDNESS);
+                    }
+
+                    wants_melee.insert(entity, components::WantsToMelee{ target: prey.entity }).expect("Unable to insert attack");
+                } else {
+                    destination = map.step_towards_as((pos.x, pos.y), (prey.x, prey.y), Some(faction.kind));
+                }
+            } else if let Some(trader) = trader {
+                if trader.distance < 1.5 && player.get(trader.entity).is_some() {
+                    messages::post(format!("{} offers to trade", name.name));
+                }
+            } else if let Some(sound) = investigating.get(entity) {
+                // Nothing in sight, so go and find out what made that noise
+                let distance = rltk::DistanceAlg::Pythagoras.distance2d(here, rltk::Point::new(sound.x, sound.y));
+                destination = map.step_towards_as((pos.x, pos.y), (sound.x, sound.y), Some(faction.kind));
+
+                if distance < 1.5 || destination.is_none() {
+                    investigating.remove(entity);
+                }
+            }
+
+            // Deep water and closed doors can hold us up for a turn
+            if let Some((x, y)) = destination {
+                if !map.can_enter(map.xy_idx(x, y)) || !doors::try_pass(&mut *map, &mut doors, &mut noises, x, y, Some(faction.kind)) || !fluids::wade(&map, &mut wading, entity, map.xy_idx(x, y)) {
+                    destination = None;
+                }
+            }
+
+            if let Some((x, y)) = destination {
+                let old_idx = map.xy_idx(pos.x, pos.y);
+                let new_idx = map.xy_idx(x, y);
+                map.blocked[old_idx] = false;
+                map.blocked[new_idx] = true;
+
+                pos.x = x;
+                pos.y = y;
+                viewshed.dirty = true;
+            }
+        }
+    }
+}
+
+pub struct MeleeCombatSystem {}
+
+impl<'a> System<'a> for MeleeCombatSystem {
+    type SystemData = ( WriteExpect<'a, noise::NoiseQueue>,
+                        Entities<'a>,
+                        WriteStorage<'a, components::WantsToMelee>,
+                        ReadStorage<'a, components::Name>,
+                        ReadStorage<'a, components::CombatStats>,
+                        ReadStorage<'a, components::Position>,
+                        WriteStorage<'a, components::SufferDamage>,
+                        WriteStorage<'a, components::Skills>,
+                        ReadStorage<'a, components::CarriedBy>,
+                        ReadStorage<'a, components::Weapon>,
+                        ReadStorage<'a, components::Armour>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut noises, entities, mut wants_melee, names, combat_stats, positions, mut inflict_damage, mut skills,
+             carried, weapons, armour) = data;
+
+        for (entity, wants_melee, name, stats, pos) in (&entities, &wants_melee, &names, &combat_stats, &positions).join() {
+            if stats.hp <= 0 {
+                continue;
+            }
+
+            if let Some(target_stats) = combat_stats.get(wants_melee.target) {
+                if target_stats.hp <= 0 {
+                    continue;
+                }
+
+                let target_name = names.get(wants_melee.target).map_or("something", |n| n.name.as_str());
+                noises.emit(pos.x, pos.y, noise::COMBAT_LOUDNESS);
+
+                // Every two levels of combat skill land an extra point of damage, on top of whatever weapons and armour are carried
+                let combat = skills.get(entity).map_or(0, |s| s.level(skills::SkillKind::Combat));
+                let (weapon, _) = materials::equipment(entity, &carried, &weapons, &armour);
+                let (_, protection) = materials::equipment(wants_melee.target, &carried, &weapons, &armour);
+                let damage = i32::max(0, stats.power + weapon + combat / 2 - target_stats.defense - protection);
+                skills::train(&mut skills, &names, entity, skills::SkillKind::Combat, skills::EXPERIENCE_PER_ACTION);
+
+                if damage == 0 {
+                    messages::post(format!("{} is unable to hurt {}", name.name, target_name));
+                } else {
+                    messages::post(format!("{} hits {} for {} hp", name.name, target_name, damage));
+                    components::SufferDamage::new_damage(&mut inflict_damage, wants_melee.target, damage);
+                }
+            }
+        }
+
+        wants_melee.clear();
+    }
+}
+
+/// Keeps carried items with whoever carries them, and leaves them lying where their carrier died.
+pub struct CarrySystem {}
+
+impl<'a> System<'a> for CarrySystem {
+    type SystemData = ( Entities<'a>,
+                        WriteStorage<'a, components::Position>,
+                        WriteStorage<'a, components::CarriedBy>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (entities, mut positions, mut carried) = data;
+
+        let carrying: Vec<(Entity, Entity)> = (&entities, &carried).join().map(|(item, c)| (item, c.owner)).collect();
+
+        for (item, owner) in carrying {
+            if !entities.is_alive(owner) {
+                carried.remove(item);
+                continue;
+            }
+
+            if let Some((x, y)) = positions.get(owner).map(|pos| (pos.x, pos.y)) {
+                if let Some(pos) = positions.get_mut(item) {
+                    pos.x = x;
+                    pos.y = y;
+                }
+            }
+        }
+    }
+}
+
+pub struct DamageSystem {}
+
+impl<'a> System<'a> for DamageSystem {
+    type SystemData = ( WriteStorage<'a, components::CombatStats>,
+                        WriteStorage<'a, components::SufferDamage>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut stats, mut damage) = data;
+
+        for (stats, damage) in (&mut stats, &damage).join() {
+            stats.hp -= damage.amount.iter().sum::<i32>();
+        }
+
+        damage.clear();
+    }
+}
+
+pub fn delete_the_dead(ecs: &mut World) {
+    let mut dead: Vec<Entity> = Vec::new();
+
+    {
+        let combat_stats = ecs.read_storage::<components::CombatStats>();
+        let names = ecs.read_storage::<components::Name>();
+        let entities = ecs.entities();
+
+        for (entity, stats) in (&entities, &combat_stats).join() {
+            if stats.hp < 1 {
+                if let Some(name) = names.get(entity) {
+                    messages::post(format!("{} dies", name.name));
+                }
+                dead.push(entity);
+            }
+        }
+    }
+
+    for victim in dead {
+        ecs.delete_entity(victim).expect("Unable to delete");
+    }
+}
+
+/// Walks units along A* paths to the tiles they have been ordered to.
+pub struct MoveOrderSystem {}
+
+impl<'a> System<'a> for MoveOrderSystem {
+    type SystemData = ( WriteExpect<'a, map::Map>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        WriteExpect<'a, noise::NoiseQueue>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::Name>,
+                        WriteStorage<'a, components::Position>,
+                        WriteStorage<'a, components::Viewshed>,
+                        WriteStorage<'a, components::MoveOrder>,
+                        ReadStorage<'a, components::Asleep>,
+                        ReadStorage<'a, components::Faction>,
+                        WriteStorage<'a, components::Door>,
+                        WriteStorage<'a, components::Wading>);
 
     fn run(&mut self, data : Self::SystemData) {
-        let (mut map, player_pos, mut viewshed, monster, name, mut position) = data;
+        let (mut map, scheduler, mut noises, entities, names, mut positions, mut viewsheds, mut orders, asleep, factions, mut doors, mut wading) = data;
 
-        for (viewshed, _monster, name, mut pos) in (&mut viewshed, &monster, &name, &mut position).join() {
-            for i in &viewshed.visible_tiles {
-                if i.x == player_pos.x && i.x == player_pos.x {
-                    let distance = rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(pos.x, pos.y), rltk::Point::new(player_pos.x, player_pos.y));
+        if !scheduler.is_new_turn() {
+            return;
+        }
 
-                    if distance < 1.5 {
-                        println!("{} shouts insults", name.name);
+        let mut finished: Vec<Entity> = Vec::new();
+
+        for (entity, pos, viewshed, order, _awake) in (&entities, &mut positions, &mut viewsheds, &orders, !&asleep).join() {
+            if pos.x == order.x && pos.y == order.y {
+                finished.push(entity);
+                continue;
+            }
+
+            let target_idx = map.xy_idx(order.x, order.y);
+            if map.blocked[target_idx] && rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(pos.x, pos.y), rltk::Point::new(order.x, order.y)) < 1.5 {
+                // Somebody is standing on the destination, this is as close as we get
+                finished.push(entity);
+                continue;
+            }
+
+            let faction = factions.get(entity).map(|f| f.kind);
+
+            match map.step_onto_as((pos.x, pos.y), (order.x, order.y), faction) {
+                Some((x, y)) => {
+                    if !map.can_enter(map.xy_idx(x, y)) || !doors::try_pass(&mut *map, &mut doors, &mut noises, x, y, faction) || !fluids::wade(&map, &mut wading, entity, map.xy_idx(x, y)) {
+                        continue;
                     }
 
-                    let path = rltk::a_star_search(
-                        map.point2d_to_index(rltk::Point::new(pos.x, pos.y)) as i32,
-                        map.point2d_to_index(rltk::Point::new(player_pos.x, player_pos.y)) as i32,
-                        &mut *map
-                    );
-                    if path.success && path.steps.len()>1 {
-                        pos.x = path.steps[1] as i32 % map.width;
-                        pos.y = path.steps[1] as i32 / map.width;
-                        viewshed.dirty = true;
+                    let old_idx = map.xy_idx(pos.x, pos.y);
+                    let new_idx = map.xy_idx(x, y);
+                    map.blocked[old_idx] = false;
+                    map.blocked[new_idx] = true;
+
+                    pos.x = x;
+                    pos.y = y;
+                    viewshed.dirty = true;
+                }
+                None => {
+                    if let Some(name) = names.get(entity) {
+                        messages::post(format!("{} cannot find a way there", name.name));
                     }
+                    finished.push(entity);
+                }
+            }
+        }
+
+        for entity in finished {
+            orders.remove(entity);
+        }
+    }
+}
+
+/// Explorers take a step towards the nearest unexplored tile every turn. They stop once there is nothing left
+/// within reach, or when they spot a hostile or an item they hadn't seen before.
+pub struct AutoExploreSystem {}
+
+impl<'a> System<'a> for AutoExploreSystem {
+    type SystemData = ( WriteExpect<'a, map::Map>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        ReadExpect<'a, factions::FactionTable>,
+                        WriteExpect<'a, noise::NoiseQueue>,
+                        Entities<'a>,
+                        ReadStorage<'a, components::Name>,
+                        WriteStorage<'a, components::Position>,
+                        WriteStorage<'a, components::Viewshed>,
+                        WriteStorage<'a, components::AutoExplore>,
+                        ReadStorage<'a, components::Asleep>,
+                        ReadStorage<'a, components::Faction>,
+                        ReadStorage<'a, components::CombatStats>,
+                        ReadStorage<'a, components::Item>,
+                        ReadStorage<'a, components::CarriedBy>,
+                        WriteStorage<'a, components::Door>,
+                        WriteStorage<'a, components::Wading>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut map, scheduler, faction_table, mut noises, entities, names, mut positions, mut viewsheds, mut explorers, asleep, factions,
+             combat_stats, items, carried, mut doors, mut wading) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        let creatures: Vec<(Entity, i32, i32, factions::FactionKind)> = (&entities, &positions, &factions, &combat_stats).join()
+            .filter(|(_, _, _, stats)| stats.hp > 0)
+            .map(|(entity, pos, faction, _)| (entity, pos.x, pos.y, faction.kind))
+            .collect();
+        let loose_items: Vec<(Entity, i32, i32)> = (&entities, &positions, &items, !&carried).join()
+            .map(|(entity, pos, _, _)| (entity, pos.x, pos.y))
+            .collect();
+
+        let mut finished: Vec<Entity> = Vec::new();
+
+        for (entity, pos, viewshed, explorer, _awake) in (&entities, &mut positions, &mut viewsheds, &explorers, !&asleep).join() {
+            let name = names.get(entity).map_or("Someone", |n| n.name.as_str());
+            let faction = factions.get(entity).map(|f| f.kind);
+            let in_sight = |x: i32, y: i32| viewshed.visible_tiles.iter().any(|tile| tile.x == x && tile.y == y);
+
+            let hostile = creatures.iter().find(|(other, x, y, other_faction)| {
+                *other != entity && in_sight(*x, *y) &&
+                    faction.map_or(false, |faction| faction_table.reaction(faction, *other_faction) == factions::Reaction::Attack)
+            });
+            if let Some((hostile, _, _, _)) = hostile {
+                messages::post(format!("{} spots {} and stops exploring", name, names.get(*hostile).map_or("something", |n| n.name.as_str())));
+                finished.push(entity);
+                continue;
+            }
+
+            let spotted = loose_items.iter().find(|(item, x, y)| in_sight(*x, *y) && !explorer.seen_items.contains(item));
+            if let Some((item, _, _)) = spotted {
+                messages::post(format!("{} spots {} and stops exploring", name, names.get(*item).map_or("something", |n| n.name.as_str()).to_lowercase()));
+                finished.push(entity);
+                continue;
+            }
+
+            let (x, y) = match map.step_towards_unexplored((pos.x, pos.y), faction) {
+                Some(step) => step,
+                None => {
+                    messages::post(format!("{} has explored everywhere within reach", name));
+                    finished.push(entity);
+                    continue;
                 }
+            };
+
+            let new_idx = map.xy_idx(x, y);
+            if map.blocked[new_idx] || !map.can_enter(new_idx) || !doors::try_pass(&mut *map, &mut doors, &mut noises, x, y, faction) || !fluids::wade(&map, &mut wading, entity, new_idx) {
+                continue;
             }
 
+            let old_idx = map.xy_idx(pos.x, pos.y);
+            map.blocked[old_idx] = false;
+            map.blocked[new_idx] = true;
+
+            pos.x = x;
+            pos.y = y;
+            viewshed.dirty = true;
+        }
+
+        for entity in finished {
+            explorers.remove(entity);
         }
     }
 }
@@ -72,6 +465,8 @@ impl<'a> System<'a> for VisibilitySystem {
 
     fn run(&mut self, data : Self::SystemData) {
         let (mut map, entities, mut viewshed, pos, player) = data;
+        let mut player_vision_changed = false;
+        let smoky = map.smoke.iter().any(|smoke| *smoke > 0);
 
         for (ent,viewshed,pos) in (&entities, &mut viewshed, &pos).join() {
             if viewshed.dirty {
@@ -80,21 +475,30 @@ impl<'a> System<'a> for VisibilitySystem {
                 viewshed.visible_tiles = rltk::field_of_view(rltk::Point::new(pos.x, pos.y), viewshed.range, &*map);
                 viewshed.visible_tiles.retain(|p| p.x >= 0 && p.x < map.width && p.y >= 0 && p.y < map.height);
 
-                // If this is the player, reveal what they can see
-                let p : Option<&components::Player> = player.get(ent);
-
-                if let Some(_p) = p {
-                    for t in map.visible_map.iter_mut() { *t = false };
-                    for vis in viewshed.visible_tiles.iter() {
-                        let idx = map.point2d_to_index(rltk::Point::new(vis.x, vis.y));
-                        map.revealed_map[idx] = true;
-                        map.visible_map[idx] = true;
-                    }
+                // Thick smoke hides what is behind it
+                if smoky {
+                    viewshed.visible_tiles.retain(|p| !heat::smoke_blocks_sight(&map, (pos.x, pos.y), (p.x, p.y)));
                 }
 
+                if player.get(ent).is_some() {
+                    player_vision_changed = true;
+                }
             }
             
         }
+
+        // The colony shares its vision, so reveal what any of the player's units can see
+        if player_vision_changed {
+            for t in map.visible_map.iter_mut() { *t = false };
+
+            for (viewshed, _player) in (&viewshed, &player).join() {
+                for vis in viewshed.visible_tiles.iter() {
+                    let idx = map.point2d_to_index(rltk::Point::new(vis.x, vis.y));
+                    map.revealed_map[idx] = true;
+                    map.visible_map[idx] = true;
+                }
+            }
+        }
     }
 }
 
@@ -103,12 +507,14 @@ pub struct GlyphMapper {}
 impl<'a> System<'a> for GlyphMapper {
     type SystemData = ( WriteExpect<'a, map::Map>,
                         WriteStorage<'a, components::Renderable>, 
-                        WriteStorage<'a, components::Position>);
+                        WriteStorage<'a, components::Position>,
+                        ReadStorage<'a, components::Tile>);
 
     fn run(&mut self, data : Self::SystemData) {
-        let (mut map, renderable, pos) = data;
+        let (mut map, renderable, pos, tiles) = data;
 
-        for (renderable, pos) in (&renderable, &pos).join() {
+        // Only the terrain goes into the glyph map, creatures, items and doors are tracked elsewhere
+        for (renderable, pos, _tile) in (&renderable, &pos, &tiles).join() {
             let coords = rltk::Point::new(pos.x, pos.y);
             let index = map.point2d_to_index(coords);
 
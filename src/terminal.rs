@@ -0,0 +1,368 @@
+use quicksilver::prelude::*;
+
+use std::fs::{ File, OpenOptions };
+use std::io::{ self, Read, Write };
+use std::process::{ Command, Stdio };
+use std::sync::mpsc::{ channel, Receiver };
+
+use crate::input::{ self, Action, Binding };
+use crate::map;
+use crate::render::Renderer;
+
+/// How many layout pixels a character cell stands for. Characters are about twice as tall as they are wide, and at
+/// this size text takes up about as much room as it does in the window, so the panels lay out the same way.
+pub static CELL_PIXELS: (f32, f32) = (8.0, 16.0);
+/// Fills at least this opaque hide the glyphs underneath them, like panel backgrounds and overlays do in the window.
+static OPAQUE_FILL: f32 = 0.8;
+/// Shaded glyphs are mixed with this rather than with the black behind them, so remembered tiles stay readable.
+static SHADE_GREY: (u8, u8, u8) = (150, 150, 150);
+/// Frames drawn between redrawing every cell rather than just the rows that changed. This cleans up after anything else
+/// that wrote to the terminal.
+static FULL_REDRAW_FRAMES: i32 = 60;
+/// Frames drawn between checking whether the terminal has been resized.
+static RESIZE_CHECK_FRAMES: i32 = 30;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+struct Cell {
+    glyph: char,
+    foreground: (u8, u8, u8),
+    background: (u8, u8, u8),
+}
+
+static BLANK: Cell = Cell { glyph: ' ', foreground: (255, 255, 255), background: (0, 0, 0) };
+
+/// A translucent colour over another.
+fn blend(under: (u8, u8, u8), over: Color) -> (u8, u8, u8) {
+    let mix = |under: u8, channel: f32| (under as f32 * (1.0 - over.a) + channel * 255.0 * over.a) as u8;
+    (mix(under.0, over.r), mix(under.1, over.g), mix(under.2, over.b))
+}
+
+/// Runs `stty` on the terminal, whatever stdin and stdout have been redirected to.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty").args(args).stdin(File::open("/dev/tty")?).stderr(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("stty {} failed", args.join(" "))));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The key a byte typed into the terminal stands for. Shifted symbols count as shift and the key they are on,
+/// going by a US layout.
+fn key_for_byte(byte: u8) -> Option<Binding> {
+    let c = byte as char;
+    let name = match c {
+        'a'..='z' => c.to_ascii_uppercase().to_string(),
+        'A'..='Z' => format!("Shift+{}", c),
+        '0'..='9' => c.to_string(),
+        _ => match ")!@#$%^&*(".find(c) {
+            Some(digit) => format!("Shift+{}", digit),
+            None => match c {
+                '.' => "Period", '>' => "Shift+Period",
+                ',' => "Comma", '<' => "Shift+Comma",
+                '/' => "Slash", '?' => "Shift+Slash",
+                ';' => "Semicolon", ':' => "Shift+Semicolon",
+                '-' => "Minus", '_' => "Shift+Minus",
+                '=' => "Equals", '+' => "Shift+Equals",
+                ' ' => "Space",
+                '\r' | '\n' => "Return",
+                '\t' => "Tab",
+                '\x1b' => "Escape",
+                '\x08' | '\x7f' => "Back",
+                _ => return None
+            }.to_string()
+        }
+    };
+
+    input::parse_binding(&name)
+}
+
+/// The key an escape sequence stands for, given the bytes after `ESC [`. Arrows come as `A` to `D`, or as `1;2A`
+/// and so on with shift held.
+fn key_for_sequence(sequence: &[u8]) -> Option<Binding> {
+    let text = String::from_utf8_lossy(sequence);
+    let shift = text.contains(";2");
+    let name = match text.trim_start_matches(|c: char| c.is_ascii_digit() || c == ';') {
+        "A" => "Up",
+        "B" => "Down",
+        "C" => "Right",
+        "D" => "Left",
+        "H" => "Home",
+        "F" => "End",
+        "~" => match text.split(|c: char| c == ';' || c == '~').next() {
+            Some("1") | Some("7") => "Home",
+            Some("4") | Some("8") => "End",
+            Some("5") => "PageUp",
+            Some("6") => "PageDown",
+            _ => return None
+        },
+        _ => return None
+    };
+
+    input::parse_binding(name).map(|binding| Binding { key: binding.key, shift })
+}
+
+/// The terminal the game is played in: what is on its screen, cell by cell, and what is typed into it.
+/// It draws to `/dev/tty`, so the game's messages can still be sent somewhere else by redirecting stdout.
+pub struct Terminal {
+    tty: File,
+    typed: Receiver<u8>,
+    /// `stty` settings from before the game started, put back when it ends.
+    saved_mode: String,
+    /// In columns and rows.
+    pub size: (i32, i32),
+    cells: Vec<Cell>,
+    /// What was on the screen after the last frame, to only send what has changed since.
+    shown: Vec<Cell>,
+    frame: i32,
+    /// Modifiers typed on their own. A terminal can't tell when a key is held down, so they stick to the next key instead.
+    sticky: Vec<Action>,
+    /// An escape, or the start of an escape sequence, that came at the very end of a frame's bytes. It is held back
+    /// for a frame in case the rest of a sequence is still on its way.
+    pending: Vec<u8>,
+}
+
+impl Terminal {
+    /// Takes over the terminal: keys arrive as they are typed without being echoed, and the game gets a screen of
+    /// its own that is put away again when it ends.
+    pub fn open() -> io::Result<Terminal> {
+        let saved_mode = stty(&["-g"])?.trim().to_string();
+        stty(&["raw", "-echo"])?;
+
+        let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        let mut reader = tty.try_clone()?;
+        let (sender, typed) = channel();
+
+        // Reading blocks until something is typed, so it happens off to the side and the game picks up whatever
+        // has arrived every frame
+        std::thread::spawn(move || {
+            let mut byte = [0u8; 1];
+            while let Ok(1) = reader.read(&mut byte) {
+                if sender.send(byte[0]).is_err() {
+                    break;
+                }
+            }
+        });
+
+        write!(tty, "\x1b[?1049h\x1b[?25l")?;
+
+        let mut terminal = Terminal { tty, typed, saved_mode, size: (0, 0), cells: Vec::new(), shown: Vec::new(), frame: 0, sticky: Vec::new(), pending: Vec::new() };
+        terminal.check_size();
+        Ok(terminal)
+    }
+
+    /// Picks up the terminal's size, returning whether it changed.
+    fn check_size(&mut self) -> bool {
+        let size = stty(&["size"]).ok().and_then(|size| {
+            let numbers: Vec<i32> = size.split_whitespace().filter_map(|n| n.parse().ok()).collect();
+            match numbers.as_slice() {
+                [rows, columns] if *rows > 0 && *columns > 0 => Some((*columns, *rows)),
+                _ => None
+            }
+        }).unwrap_or((80, 24));
+
+        if size == self.size {
+            return false;
+        }
+
+        self.size = size;
+        self.cells = vec![BLANK; (size.0 * size.1) as usize];
+        self.shown = Vec::new();
+        true
+    }
+
+    /// Every so often, checks whether the terminal has been resized since the screen was laid out.
+    pub fn resized(&mut self) -> bool {
+        self.frame % RESIZE_CHECK_FRAMES == 0 && self.check_size()
+    }
+
+    /// The terminal's size in layout pixels.
+    pub fn size_pixels(&self) -> Vector {
+        Vector::new(self.size.0 as f32 * CELL_PIXELS.0, self.size.1 as f32 * CELL_PIXELS.1)
+    }
+
+    /// Turns what has been typed since the last frame into actions. Returns false if the player pressed Ctrl+C,
+    /// which in raw mode comes through as a key rather than stopping the game.
+    pub fn read_keys(&mut self, queue: &mut input::InputQueue, keymap: &input::Keymap) -> bool {
+        let arrived: Vec<u8> = self.typed.try_iter().collect();
+        // Anything held back last frame has waited long enough once a frame goes by with nothing more typed
+        let waited = arrived.is_empty();
+        let mut typed = std::mem::replace(&mut self.pending, Vec::new());
+        typed.extend(arrived);
+        let mut bytes = typed.into_iter().peekable();
+
+        while let Some(byte) = bytes.next() {
+            let binding = match byte {
+                3 => return false,
+                // On its own an escape is the Escape key, but it may be the start of a sequence that isn't all here yet
+                0x1b if bytes.peek().is_none() && !waited => {
+                    self.pending.push(byte);
+                    break;
+                }
+                0x1b if bytes.peek() == Some(&b'[') || bytes.peek() == Some(&b'O') => {
+                    let introducer = bytes.next().unwrap_or(b'[');
+                    let mut sequence = Vec::new();
+                    let mut finished = false;
+                    for byte in bytes.by_ref() {
+                        sequence.push(byte);
+                        if (0x40..=0x7e).contains(&byte) {
+                            finished = true;
+                            break;
+                        }
+                    }
+
+                    if !finished && !waited {
+                        self.pending.extend(&[byte, introducer]);
+                        self.pending.extend(sequence);
+                        break;
+                    }
+                    key_for_sequence(&sequence)
+                }
+                _ => key_for_byte(byte)
+            };
+
+            let action = match binding.and_then(|binding| keymap.resolve(binding)) {
+                Some(action) => action,
+                None => continue
+            };
+
+            if action.is_modifier() {
+                if !self.sticky.contains(&action) {
+                    self.sticky.push(action);
+                }
+                continue;
+            }
+
+            for modifier in self.sticky.drain(..) {
+                queue.hold(modifier);
+            }
+            queue.inject(action);
+        }
+
+        true
+    }
+
+    pub fn clear(&mut self) {
+        for cell in self.cells.iter_mut() {
+            *cell = BLANK;
+        }
+    }
+
+    /// Sends the rows that changed since the last frame to the terminal.
+    pub fn present(&mut self) -> io::Result<()> {
+        self.frame += 1;
+
+        let columns = self.size.0 as usize;
+        let full = self.shown.len() != self.cells.len() || self.frame % FULL_REDRAW_FRAMES == 0;
+        let mut out = String::new();
+        let mut colours = None;
+
+        for (row, line) in self.cells.chunks(columns).enumerate() {
+            if !full && line == &self.shown[row * columns..(row + 1) * columns] {
+                continue;
+            }
+
+            out.push_str(&format!("\x1b[{};1H", row + 1));
+            for cell in line {
+                if colours != Some((cell.foreground, cell.background)) {
+                    let (fg, bg) = (cell.foreground, cell.background);
+                    out.push_str(&format!("\x1b[38;2;{};{};{};48;2;{};{};{}m", fg.0, fg.1, fg.2, bg.0, bg.1, bg.2));
+                    colours = Some((fg, bg));
+                }
+                out.push(cell.glyph);
+            }
+        }
+
+        self.shown = self.cells.clone();
+        self.tty.write_all(out.as_bytes())?;
+        self.tty.flush()
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && x < self.size.0 && y < self.size.1 { Some((y * self.size.0 + x) as usize) } else { None }
+    }
+
+    /// The cell a layout pixel falls in, rounding to the nearest cell.
+    fn cell_at(&self, position: Vector) -> (i32, i32) {
+        ((position.x / CELL_PIXELS.0).round() as i32, (position.y / CELL_PIXELS.1).round() as i32)
+    }
+
+    /// The cells an area covers. Its edges are rounded to the nearest cell edges, and anything with a size
+    /// covers at least one cell.
+    fn cells_in(&self, area: Rectangle) -> Vec<usize> {
+        let span = |start: f32, length: f32, cell: f32| {
+            let first = (start / cell).round() as i32;
+            let last = if length <= 0.0 { first } else { i32::max(first + 1, ((start + length) / cell).round() as i32) };
+            first..last
+        };
+
+        let (columns, rows) = (span(area.pos.x, area.size.x, CELL_PIXELS.0), span(area.pos.y, area.size.y, CELL_PIXELS.1));
+        rows.flat_map(|y| columns.clone().map(move |x| (x, y)))
+            .filter_map(|(x, y)| self.index(x, y))
+            .collect()
+    }
+}
+
+impl Renderer for Terminal {
+    fn fill(&mut self, area: Rectangle, colour: Color) -> Result<()> {
+        for idx in self.cells_in(area) {
+            let cell = &mut self.cells[idx];
+            cell.background = blend(cell.background, colour);
+            if colour.a >= OPAQUE_FILL {
+                cell.glyph = ' ';
+            }
+        }
+
+        Ok(())
+    }
+
+    fn line(&mut self, from: Vector, to: Vector, _thickness: f32, colour: Color) -> Result<()> {
+        let (start, end) = (self.cell_at(from), self.cell_at(to));
+        let glyph = if (end.0 - start.0).abs() < (end.1 - start.1).abs() { '│' } else { '─' };
+
+        for (x, y) in map::line(start, end) {
+            if let Some(idx) = self.index(x, y) {
+                let cell = &mut self.cells[idx];
+                cell.glyph = glyph;
+                cell.foreground = blend(cell.background, colour);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tile(&mut self, area: Rectangle, glyph: char, _name: Option<&str>, colour: Color) -> Result<()> {
+        let (x, y) = self.cell_at(area.pos);
+
+        if let Some(idx) = self.index(x, y) {
+            let cell = &mut self.cells[idx];
+            cell.glyph = glyph;
+            cell.foreground = blend(SHADE_GREY, colour);
+        }
+
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str, position: Vector, _font_size: f32, colour: Color) -> Result<()> {
+        let (x, y) = self.cell_at(position);
+
+        for (offset, glyph) in text.chars().enumerate() {
+            if let Some(idx) = self.index(x + offset as i32, y) {
+                let cell = &mut self.cells[idx];
+                cell.glyph = glyph;
+                cell.foreground = blend(cell.background, colour);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Puts the terminal back the way it was found.
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = write!(self.tty, "\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = self.tty.flush();
+        let _ = stty(&[&self.saved_mode]);
+    }
+}
@@ -0,0 +1,186 @@
+use quicksilver::prelude::*;
+use quicksilver::load_file;
+
+use std::collections::HashMap;
+
+use crate::messages;
+use crate::raws;
+
+static TILESETS_RAW: &str = include_str!("../static/tilesets.txt");
+/// Drawn for anything the tileset has no tile for.
+pub static FALLBACK_GLYPH: char = '?';
+/// Code page 437 sheets are laid out 16 glyphs to a row.
+static CP437_COLUMNS: i32 = 16;
+/// Where the few non-ASCII characters a CP437 sheet is likely to be asked for sit on it.
+static CP437_EXTRAS: [(char, i32); 8] = [('☺', 1), ('♥', 3), ('♣', 5), ('░', 176), ('▒', 177), ('▓', 178), ('≈', 247), ('·', 250)];
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum TilesetKind { Font, Cp437, Sprites }
+
+impl TilesetKind {
+    pub fn from_name(name: &str) -> Option<TilesetKind> {
+        match name {
+            "font" => Some(TilesetKind::Font),
+            "cp437" => Some(TilesetKind::Cp437),
+            "sprites" => Some(TilesetKind::Sprites),
+            _ => None
+        }
+    }
+}
+
+/// A line of `static/tilesets.txt`.
+#[derive(Clone, Debug)]
+pub struct TilesetDefinition {
+    pub name: String,
+    pub kind: TilesetKind,
+    /// Font or image file, looked for in `static/`.
+    pub file: String,
+    /// Size of a tile on the sheet, in pixels. Tiles are scaled to the map's tile size when they are drawn.
+    pub tile_size: Vector,
+    /// Sprite sheets only: the file saying which sprite goes with which glyph or name.
+    pub mapping: Option<String>,
+}
+
+pub struct Tileset {
+    pub name: String,
+    /// Font and CP437 tiles are white and get tinted with whatever colour the thing is drawn in,
+    /// sprites are drawn as they are.
+    pub tinted: bool,
+    glyphs: HashMap<char, Image>,
+    /// Sprites for particular creatures and items, by `Name`. These win over the glyph.
+    named: HashMap<String, Image>,
+    fallback: Option<Image>,
+}
+
+impl Tileset {
+    /// The tile for something, going by its name first and its glyph second. Falls back to `FALLBACK_GLYPH`
+    /// rather than drawing nothing.
+    pub fn get(&self, glyph: char, name: Option<&str>) -> Option<&Image> {
+        name.and_then(|name| self.named.get(name))
+            .or_else(|| self.glyphs.get(&glyph))
+            .or_else(|| self.fallback.as_ref())
+    }
+}
+
+pub fn definitions() -> Vec<TilesetDefinition> {
+    raws::data_lines(TILESETS_RAW).iter().map(|fields| {
+        if fields.len() < 3 {
+            panic!("Tilesets need a name, kind and file: {:?}", fields);
+        }
+
+        let kind = TilesetKind::from_name(fields[1]).unwrap_or_else(|| panic!("Unknown tileset kind '{}'", fields[1]));
+        let tile_size = match fields.get(3).map(|size| size.split(',').map(|n| n.trim().parse::<i32>()).collect::<Vec<_>>()) {
+            Some(size) => match size.as_slice() {
+                [Ok(width), Ok(height)] => Vector::new(*width, *height),
+                _ => panic!("Tile sizes are given as width,height: {:?}", fields)
+            },
+            None => Vector::new(0, 0)
+        };
+
+        if kind != TilesetKind::Font && tile_size.x <= 0.0 {
+            panic!("Image tilesets need a tile size: {}", fields[0]);
+        }
+        if kind == TilesetKind::Sprites && fields.get(4).is_none() {
+            panic!("Sprite sheets need a mapping file: {}", fields[0]);
+        }
+
+        TilesetDefinition {
+            name: fields[0].to_string(),
+            kind,
+            file: fields[2].to_string(),
+            tile_size,
+            mapping: fields.get(4).map(|mapping| mapping.to_string()),
+        }
+    }).collect()
+}
+
+/// Starts loading a tileset. If its files can't be loaded, the font tileset is used in its place, so a missing
+/// sheet doesn't stop the game from drawing.
+pub fn load(definition: &TilesetDefinition, font_file: &str, tile_pixels: Vector) -> Asset<Tileset> {
+    let name = definition.name.clone();
+    let font_file = font_file.to_string();
+    let fallback = move |error: Error| {
+        messages::post(format!("Could not load the {} tileset ({}), using {} instead", name, error, font_file));
+        load_font(font_file.clone(), font_file, tile_pixels)
+    };
+
+    match definition.kind {
+        TilesetKind::Font => Asset::new(load_font(definition.name.clone(), definition.file.clone(), tile_pixels).or_else(fallback)),
+        TilesetKind::Cp437 => Asset::new(load_cp437(definition).or_else(fallback)),
+        TilesetKind::Sprites => Asset::new(load_sprites(definition).or_else(fallback)),
+    }
+}
+
+/// Every printable ASCII character, rendered from a font. Each glyph is rendered on its own, so it comes out as wide
+/// as the font says it is rather than being cut out of a strip at the tile width. Narrow fonts are stretched to fill
+/// their tiles when they are drawn.
+fn load_font(name: String, file: String, tile_pixels: Vector) -> impl Future<Item = Tileset, Error = Error> {
+    Font::load(file).and_then(move |font| {
+        let style = FontStyle::new(tile_pixels.y, Color::WHITE);
+
+        let mut tileset = Tileset { name, tinted: true, glyphs: HashMap::new(), named: HashMap::new(), fallback: None };
+        for glyph in ' '..='~' {
+            tileset.glyphs.insert(glyph, font.render(&glyph.to_string(), &style)?);
+        }
+        tileset.fallback = tileset.glyphs.get(&FALLBACK_GLYPH).cloned();
+
+        Ok(tileset)
+    })
+}
+
+fn sheet_tile(sheet: &Image, tile_size: Vector, column: i32, row: i32) -> Image {
+    sheet.subimage(Rectangle::new(Vector::new(column, row).times(tile_size), tile_size))
+}
+
+/// A bitmap font laid out in code page 437 order. ASCII sits where it does in CP437, so it maps straight across.
+fn load_cp437(definition: &TilesetDefinition) -> impl Future<Item = Tileset, Error = Error> {
+    let name = definition.name.clone();
+    let tile_size = definition.tile_size;
+
+    Image::load(definition.file.clone()).map(move |sheet| {
+        let mut tileset = Tileset { name, tinted: true, glyphs: HashMap::new(), named: HashMap::new(), fallback: None };
+        let codes = (' '..='~').map(|glyph| (glyph, glyph as i32)).chain(CP437_EXTRAS.iter().cloned());
+
+        for (glyph, code) in codes {
+            tileset.glyphs.insert(glyph, sheet_tile(&sheet, tile_size, code % CP437_COLUMNS, code / CP437_COLUMNS));
+        }
+        tileset.fallback = tileset.glyphs.get(&FALLBACK_GLYPH).cloned();
+
+        tileset
+    })
+}
+
+/// A sheet of pictures, with a mapping file of `identifier | column,row` lines. An identifier of a single character
+/// is a glyph, anything longer is the name of a creature or item. A mapping file with a mistake in it fails the load
+/// like a missing file would.
+fn load_sprites(definition: &TilesetDefinition) -> impl Future<Item = Tileset, Error = Error> {
+    let name = definition.name.clone();
+    let tile_size = definition.tile_size;
+    let mapping = definition.mapping.clone().unwrap_or_default();
+
+    Image::load(definition.file.clone()).join(load_file(mapping)).and_then(move |(sheet, mapping)| {
+        let mut tileset = Tileset { name, tinted: false, glyphs: HashMap::new(), named: HashMap::new(), fallback: None };
+        let mapping = String::from_utf8_lossy(&mapping).to_string();
+
+        for fields in raws::data_lines(&mapping) {
+            let cell: Vec<i32> = fields.get(1).map_or(Vec::new(), |cell| cell.split(',').filter_map(|n| n.trim().parse().ok()).collect());
+            let (column, row) = match cell.as_slice() {
+                [column, row] => (*column, *row),
+                _ => {
+                    let message = format!("sprites are given as identifier | column,row: {:?}", fields);
+                    return Err(Error::IOError(std::io::Error::new(std::io::ErrorKind::InvalidData, message)));
+                }
+            };
+
+            let sprite = sheet_tile(&sheet, tile_size, column, row);
+            let mut chars = fields[0].chars();
+            match (chars.next(), chars.next()) {
+                (Some(glyph), None) => { tileset.glyphs.insert(glyph, sprite); }
+                _ => { tileset.named.insert(fields[0].to_string(), sprite); }
+            }
+        }
+        tileset.fallback = tileset.glyphs.get(&FALLBACK_GLYPH).cloned();
+
+        Ok(tileset)
+    })
+}
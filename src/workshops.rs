@@ -0,0 +1,167 @@
+use specs::prelude::*;
+
+use crate::components;
+use crate::jobs;
+use crate::map;
+use crate::raws;
+use crate::scheduler;
+
+/// Workshops are square, centred on their entity's `Position`.
+pub static WORKSHOP_SIZE: i32 = 3;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum WorkshopKind { Mason, Smith, Carpenter, Kitchen }
+
+pub static WORKSHOP_KINDS: [WorkshopKind; 4] = [WorkshopKind::Mason, WorkshopKind::Smith, WorkshopKind::Carpenter, WorkshopKind::Kitchen];
+
+impl WorkshopKind {
+    pub fn from_name(name: &str) -> Option<WorkshopKind> {
+        match name {
+            "mason" => Some(WorkshopKind::Mason),
+            "smith" => Some(WorkshopKind::Smith),
+            "carpenter" => Some(WorkshopKind::Carpenter),
+            "kitchen" => Some(WorkshopKind::Kitchen),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkshopKind::Mason => "Mason's workshop",
+            WorkshopKind::Smith => "Smithy",
+            WorkshopKind::Carpenter => "Carpenter's workshop",
+            WorkshopKind::Kitchen => "Kitchen",
+        }
+    }
+
+    pub fn glyph(&self) -> char {
+        match self {
+            WorkshopKind::Mason => 'M',
+            WorkshopKind::Smith => 'S',
+            WorkshopKind::Carpenter => 'C',
+            WorkshopKind::Kitchen => 'K',
+        }
+    }
+}
+
+pub fn footprint(x: i32, y: i32) -> Vec<(i32, i32)> {
+    let half = WORKSHOP_SIZE / 2;
+    let mut tiles = Vec::new();
+
+    for ty in y - half..=y + half {
+        for tx in x - half..=x + half {
+            tiles.push((tx, ty));
+        }
+    }
+
+    tiles
+}
+
+pub fn in_footprint(centre_x: i32, centre_y: i32, x: i32, y: i32) -> bool {
+    let half = WORKSHOP_SIZE / 2;
+    (x - centre_x).abs() <= half && (y - centre_y).abs() <= half
+}
+
+/// Finds the workshop whose footprint covers a tile.
+pub fn workshop_at(ecs: &World, x: i32, y: i32) -> Option<Entity> {
+    let entities = ecs.entities();
+    let workshops = ecs.read_storage::<components::Workshop>();
+    let positions = ecs.read_storage::<components::Position>();
+
+    (&entities, &workshops, &positions).join()
+        .find(|(_, _, pos)| in_footprint(pos.x, pos.y, x, y))
+        .map(|(entity, _, _)| entity)
+}
+
+/// Lays out an unbuilt workshop centred on a tile and posts the job to build it.
+/// Every tile of the footprint has to be open floor that no other workshop is using.
+pub fn place_workshop(ecs: &mut World, kind: WorkshopKind, x: i32, y: i32) -> bool {
+    {
+        let map = ecs.fetch::<map::Map>();
+
+        for (tx, ty) in footprint(x, y) {
+            if !map.in_bounds(tx, ty) || map.glyph_map[map.xy_idx(tx, ty)] == '#' || workshop_at(ecs, tx, ty).is_some() {
+                return false;
+            }
+        }
+    }
+
+    let workshop = ecs.create_entity()
+        .with(components::Position{ x, y })
+        .with(components::Renderable{ glyph: kind.glyph(), color: quicksilver::prelude::Color::from_rgba(110, 70, 30, 1.0) })
+        .with(components::Name{ name: kind.name().to_string() })
+        .with(components::Workshop{ kind, built: false, queue: Vec::new(), selected_recipe: 0, active_job: None })
+        .build();
+
+    let mut board = ecs.write_resource::<jobs::JobBoard>();
+    if let Some(job_id) = board.designate(jobs::JobKind::Build, x, y) {
+        if let Some(job) = board.get_mut(job_id) {
+            job.target = Some(workshop);
+        }
+    }
+
+    true
+}
+
+/// Picks ingredients for the first order in each workshop's queue, has them hauled over and posts the craft job.
+pub struct WorkshopSystem {}
+
+impl<'a> System<'a> for WorkshopSystem {
+    type SystemData = ( ReadExpect<'a, raws::Raws>,
+                        WriteExpect<'a, jobs::JobBoard>,
+                        ReadExpect<'a, scheduler::Scheduler>,
+                        Entities<'a>,
+                        WriteStorage<'a, components::Workshop>,
+                        ReadStorage<'a, components::Position>,
+                        ReadStorage<'a, components::Name>,
+                        ReadStorage<'a, components::Item>,
+                        ReadStorage<'a, components::CarriedBy>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (raws, mut board, scheduler, entities, mut workshops, positions, names, items, carried) = data;
+
+        if !scheduler.is_new_turn() {
+            return;
+        }
+
+        let mut reserved = board.reserved_items();
+        let loose_items: Vec<(Entity, String, i32, i32)> = (&entities, &items, &positions, &names, !&carried).join()
+            .map(|(item, _, pos, name, _)| (item, name.name.clone(), pos.x, pos.y))
+            .collect();
+
+        for (entity, workshop, pos) in (&entities, &mut workshops, &positions).join() {
+            if !workshop.built {
+                continue;
+            }
+
+            if let Some(job_id) = workshop.active_job {
+                if board.get(job_id).is_some() {
+                    continue;
+                }
+                workshop.active_job = None;
+            }
+
+            let recipe_id = match workshop.queue.first() {
+                Some(recipe_id) => *recipe_id,
+                None => continue
+            };
+            let recipe = &raws.recipes[recipe_id];
+
+            let ingredients = match jobs::find_ingredients(&recipe.inputs, (pos.x, pos.y), &reserved, &loose_items) {
+                Some(ingredients) => ingredients,
+                None => continue
+            };
+
+            let job_id = board.post_craft(entity, recipe_id, (pos.x, pos.y), ingredients.iter().map(|(item, _, _)| *item).collect(), recipe.turns);
+            workshop.active_job = Some(job_id);
+
+            for (item, x, y) in ingredients {
+                reserved.push(item);
+
+                if !in_footprint(pos.x, pos.y, x, y) {
+                    board.post_haul(item, (x, y), (pos.x, pos.y));
+                }
+            }
+        }
+    }
+}